@@ -1,94 +1,944 @@
 #![no_std]
 #![deny(unsafe_code)]
 
+#[cfg(feature = "sim")]
+extern crate std;
+
 use core::fmt::Debug;
-use embedded_hal::digital::{OutputPin, PinState};
+use core::ops::Range;
+use embedded_hal::digital::{Error as _, ErrorKind as PinErrorKind, OutputPin, PinState};
 use embedded_storage::nor_flash::{ErrorType, NorFlashError, NorFlashErrorKind};
+use protection::BlockProtection;
+use transport::{Instruction, Lanes};
 
+pub mod protection;
+pub mod sfdp;
+#[cfg(feature = "sim")]
+pub mod sim;
+pub mod status;
+pub mod transport;
 mod w25q32jv;
 #[cfg(feature = "async")]
 mod w25q32jv_async;
 
+#[cfg(feature = "async")]
+pub use w25q32jv_async::SuspendableErase;
+
 pub const PAGE_SIZE: u32 = 256;
+pub const SECTOR_SIZE: u32 = PAGE_SIZE * 16;
+pub const BLOCK_32K_SIZE: u32 = SECTOR_SIZE * 8;
+pub const BLOCK_64K_SIZE: u32 = BLOCK_32K_SIZE * 2;
+pub const SECURITY_REGISTER_SIZE: u32 = 256;
+
+/// The largest capacity that can be addressed with three address bytes.
+const THREE_BYTE_ADDRESS_LIMIT: u32 = 1 << 24;
+
+/// The default percentage that is added to the datasheet maximum durations before an operation times out.
+pub const DEFAULT_TIMEOUT_MARGIN: u32 = 25;
+
+/// The minimum time between resuming an erase or program and suspending it again.
+const RESUME_TO_SUSPEND_US: u32 = 20;
+
+/// The JEDEC manufacturer ID of Winbond.
+pub const WINBOND_MANUFACTURER_ID: u8 = 0xEF;
+
+/// The size of the flash memory of a chip.
+///
+/// The page, sector and block sizes are the same for all supported chips, only the number of them differs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FlashGeometry {
+    n_pages: u32,
+}
+
+impl FlashGeometry {
+    /// The geometry of the 32 M-bit W25Q32.
+    pub const W25Q32: Self = Self { n_pages: 16384 };
+    /// The geometry of the 64 M-bit W25Q64.
+    pub const W25Q64: Self = Self { n_pages: 32768 };
+    /// The geometry of the 128 M-bit W25Q128.
+    pub const W25Q128: Self = Self { n_pages: 65536 };
+    /// The geometry of the 256 M-bit W25Q256.
+    pub const W25Q256: Self = Self { n_pages: 131072 };
+    /// The geometry of the 512 M-bit W25Q512.
+    pub const W25Q512: Self = Self { n_pages: 262144 };
+
+    /// Create the geometry of a chip with the given capacity in bytes.
+    ///
+    /// Returns `None` if the capacity is not a non-zero multiple of [BLOCK_64K_SIZE].
+    pub const fn from_capacity(capacity: u32) -> Option<Self> {
+        if capacity == 0 || !capacity.is_multiple_of(BLOCK_64K_SIZE) {
+            return None;
+        }
+
+        Some(Self {
+            n_pages: capacity / PAGE_SIZE,
+        })
+    }
+
+    /// Create the geometry from the capacity that is encoded in the JEDEC ID of the chip.
+    pub const fn from_jedec_id(id: &JedecId) -> Option<Self> {
+        match id.capacity_bytes() {
+            Some(capacity) => Self::from_capacity(capacity),
+            None => None,
+        }
+    }
+
+    /// The capacity of the flash chip in bytes.
+    pub const fn capacity(&self) -> u32 {
+        self.n_pages * PAGE_SIZE
+    }
+
+    /// The number of address bytes needed to address the whole chip.
+    /// Chips larger than 128 M-bit need 4-byte addresses.
+    pub const fn address_width(&self) -> AddressWidth {
+        if self.capacity() > THREE_BYTE_ADDRESS_LIMIT {
+            AddressWidth::FourByte
+        } else {
+            AddressWidth::ThreeByte
+        }
+    }
 
-cfg_if::cfg_if! {
-    if #[cfg(feature = "megabits128")] {
-        pub const N_PAGES: u32 = 65536;
-    } else if #[cfg(feature = "megabits64")] {
-        pub const N_PAGES: u32 = 32768;
-    } else {
-        pub const N_PAGES: u32 = 16384;
+    /// The number of pages of [PAGE_SIZE].
+    pub const fn n_pages(&self) -> u32 {
+        self.n_pages
+    }
+
+    /// The number of sectors of [SECTOR_SIZE].
+    pub const fn n_sectors(&self) -> u32 {
+        self.n_pages / 16
+    }
+
+    /// The number of blocks of [BLOCK_32K_SIZE].
+    pub const fn n_blocks_32k(&self) -> u32 {
+        self.n_sectors() / 8
+    }
+
+    /// The number of blocks of [BLOCK_64K_SIZE].
+    pub const fn n_blocks_64k(&self) -> u32 {
+        self.n_blocks_32k() / 2
+    }
+
+    /// The address range that is locked as one unit by the individual block locks, containing the given address.
+    /// The first and the last 64K block are locked per sector, all other blocks as a whole.
+    pub const fn lock_unit(&self, address: u32) -> Range<u32> {
+        let block = address / BLOCK_64K_SIZE;
+
+        if block == 0 || block == self.n_blocks_64k() - 1 {
+            let start = address - address % SECTOR_SIZE;
+            start..start + SECTOR_SIZE
+        } else {
+            let start = block * BLOCK_64K_SIZE;
+            start..start + BLOCK_64K_SIZE
+        }
     }
 }
 
-pub const CAPACITY: u32 = PAGE_SIZE * N_PAGES;
+/// The number of bytes used to send an address to the chip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AddressWidth {
+    ThreeByte,
+    FourByte,
+}
 
-pub const SECTOR_SIZE: u32 = PAGE_SIZE * 16;
-pub const N_SECTORS: u32 = N_PAGES / 16;
-pub const BLOCK_32K_SIZE: u32 = SECTOR_SIZE * 8;
-pub const N_BLOCKS_32K: u32 = N_SECTORS / 8;
-pub const BLOCK_64K_SIZE: u32 = BLOCK_32K_SIZE * 2;
-pub const N_BLOCKS_64K: u32 = N_BLOCKS_32K / 2;
+impl AddressWidth {
+    /// The number of address bytes.
+    pub const fn n_bytes(&self) -> usize {
+        match self {
+            AddressWidth::ThreeByte => 3,
+            AddressWidth::FourByte => 4,
+        }
+    }
+}
+
+/// The instruction used to read data from the chip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ReadMode {
+    /// Read Data (0x03). Supports SPI clocks up to 50 MHz.
+    #[default]
+    Normal,
+    /// Fast Read (0x0B) with a dummy byte after the address. Supports SPI clocks up to 133 MHz.
+    Fast,
+    /// Fast Read Dual Output (1-1-2), the data is read on two lanes.
+    DualOutput,
+    /// Fast Read Dual I/O (1-2-2), the address and data are sent on two lanes.
+    DualIo,
+    /// Fast Read Quad Output (1-1-4), the data is read on four lanes.
+    QuadOutput,
+    /// Fast Read Quad I/O (1-4-4), the address and data are sent on four lanes.
+    QuadIo,
+}
+
+/// The number of dummy clocks of Fast Read in QPI mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum QpiDummyClocks {
+    /// Two dummy clocks, the default after power up. Limits the SPI clock the most.
+    #[default]
+    Two = 0b00,
+    Four = 0b01,
+    Six = 0b10,
+    /// Eight dummy clocks, needed for the highest SPI clocks.
+    Eight = 0b11,
+}
+
+/// The number of bytes after which a Burst Read with Wrap wraps around in QPI mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WrapLength {
+    #[default]
+    Bytes8 = 0b00,
+    Bytes16 = 0b01,
+    Bytes32 = 0b10,
+    Bytes64 = 0b11,
+}
+
+/// The read parameters that are set with the Set Read Parameters (0xC0) instruction in QPI mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ReadParameters {
+    pub dummy_clocks: QpiDummyClocks,
+    pub wrap_length: WrapLength,
+}
+
+impl ReadParameters {
+    /// The parameter byte as it's sent to the chip.
+    fn bits(&self) -> u8 {
+        ((self.dummy_clocks as u8) << 4) | self.wrap_length as u8
+    }
+
+    /// The number of dummy cycles of Fast Read.
+    fn dummy_cycles(&self) -> u8 {
+        2 + 2 * self.dummy_clocks as u8
+    }
+}
+
+/// How the status registers are protected against writes, selected with the SRP and SRL bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StatusRegisterProtection {
+    /// SRL=0, SRP=0: the status registers can be written after a Write Enable.
+    Software,
+    /// SRL=0, SRP=1: the status registers are locked while the WP pin is low.
+    Hardware,
+    /// SRL=1, SRP=0: the status registers are locked until the next power cycle.
+    PowerSupplyLockDown,
+    /// SRL=1, SRP=1: the status registers are locked permanently.
+    OneTimeProgram,
+}
+
+/// One of the three one time programmable security registers of [SECURITY_REGISTER_SIZE] bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SecurityRegister {
+    One = 1,
+    Two = 2,
+    Three = 3,
+}
+
+impl SecurityRegister {
+    /// The address of the first byte of the register.
+    pub const fn address(&self) -> u32 {
+        (*self as u32) << 12
+    }
+}
+
+/// Confirmation that locking a security register can't be undone.
+///
+/// Required by `lock_security_register` so a lock can't happen by accident.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PermanentLock(());
+
+impl PermanentLock {
+    /// Confirm that the security register can never be programmed or erased again once it's locked.
+    pub const fn confirm_irreversible() -> Self {
+        Self(())
+    }
+}
+
+/// A unit of the flash memory that can be erased with a single instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EraseUnit {
+    /// A sector of [SECTOR_SIZE] bytes.
+    Sector,
+    /// A block of [BLOCK_32K_SIZE] bytes.
+    Block32K,
+    /// A block of [BLOCK_64K_SIZE] bytes.
+    Block64K,
+}
+
+impl EraseUnit {
+    /// The number of bytes that are erased.
+    pub const fn size(&self) -> u32 {
+        match self {
+            EraseUnit::Sector => SECTOR_SIZE,
+            EraseUnit::Block32K => BLOCK_32K_SIZE,
+            EraseUnit::Block64K => BLOCK_64K_SIZE,
+        }
+    }
+
+    fn busy_operation(&self) -> BusyOperation {
+        match self {
+            EraseUnit::Sector => BusyOperation::SectorErase,
+            EraseUnit::Block32K => BusyOperation::Block32KErase,
+            EraseUnit::Block64K => BusyOperation::Block64KErase,
+        }
+    }
+}
+
+/// The number of sector and block erases the driver did and skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EraseCounters {
+    /// The number of erases that were sent to the chip.
+    pub erased: u32,
+    /// The number of erases that were skipped because the target was already blank.
+    pub skipped: u32,
+}
+
+/// An erase or program that was started and may still be in progress.
+///
+/// Returned by the `start_` functions and checked with `poll`, which verifies the result once it's done.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PendingOperation<'a> {
+    address: u32,
+    len: u32,
+    /// The programmed bytes, or `None` for an erase.
+    data: Option<&'a [u8]>,
+    kind: BusyOperation,
+}
+
+impl<'a> PendingOperation<'a> {
+    fn erase(address: u32, len: u32, kind: BusyOperation) -> Self {
+        Self {
+            address,
+            len,
+            data: None,
+            kind,
+        }
+    }
+
+    fn program(address: u32, data: &'a [u8]) -> Self {
+        Self {
+            address,
+            len: data.len() as u32,
+            data: Some(data),
+            kind: BusyOperation::PageProgram,
+        }
+    }
+
+    /// The address range that is erased or programmed.
+    pub fn range(&self) -> Range<u32> {
+        self.address..self.address + self.len
+    }
+
+    /// Whether the operation is an erase.
+    pub fn is_erase(&self) -> bool {
+        self.data.is_none()
+    }
+}
+
+/// The instructions used to erase the different erase units.
+/// An instruction is `None` when the chip doesn't support erasing that unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EraseOpcodes {
+    /// The instruction to erase [SECTOR_SIZE] bytes.
+    pub sector: Option<u8>,
+    /// The instruction to erase [BLOCK_32K_SIZE] bytes.
+    pub block_32k: Option<u8>,
+    /// The instruction to erase [BLOCK_64K_SIZE] bytes.
+    pub block_64k: Option<u8>,
+}
+
+impl EraseOpcodes {
+    /// The erase instructions of the Winbond W25Q chips.
+    pub const WINBOND: Self = Self {
+        sector: Some(Command::SectorErase as u8),
+        block_32k: Some(Command::Block32Erase as u8),
+        block_64k: Some(Command::Block64Erase as u8),
+    };
+
+    /// Get the instruction to erase the unit, if the chip supports it.
+    pub const fn opcode(&self, unit: EraseUnit) -> Option<u8> {
+        match unit {
+            EraseUnit::Sector => self.sector,
+            EraseUnit::Block32K => self.block_32k,
+            EraseUnit::Block64K => self.block_64k,
+        }
+    }
+
+    /// Get the erase instructions described by the SFDP of the chip.
+    pub fn from_sfdp(parameters: &sfdp::BasicFlashParameters) -> Self {
+        Self {
+            sector: parameters.erase_opcode(SECTOR_SIZE),
+            block_32k: parameters.erase_opcode(BLOCK_32K_SIZE),
+            block_64k: parameters.erase_opcode(BLOCK_64K_SIZE),
+        }
+    }
+}
+
+/// The multi-lane fast read instructions the chip supports.
+/// An instruction is `None` when the chip doesn't support that read mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ReadOpcodes {
+    /// Fast Read Dual Output (1-1-2).
+    pub dual_output: Option<sfdp::FastRead>,
+    /// Fast Read Dual I/O (1-2-2).
+    pub dual_io: Option<sfdp::FastRead>,
+    /// Fast Read Quad Output (1-1-4).
+    pub quad_output: Option<sfdp::FastRead>,
+    /// Fast Read Quad I/O (1-4-4).
+    pub quad_io: Option<sfdp::FastRead>,
+}
+
+impl ReadOpcodes {
+    /// The fast read instructions of the Winbond W25Q chips.
+    pub const WINBOND: Self = Self {
+        dual_output: Some(sfdp::FastRead {
+            opcode: Command::FastReadDualOutput as u8,
+            mode_clocks: 0,
+            dummy_clocks: 8,
+        }),
+        dual_io: Some(sfdp::FastRead {
+            opcode: Command::FastReadDualIo as u8,
+            mode_clocks: 4,
+            dummy_clocks: 0,
+        }),
+        quad_output: Some(sfdp::FastRead {
+            opcode: Command::FastReadQuadOutput as u8,
+            mode_clocks: 0,
+            dummy_clocks: 8,
+        }),
+        quad_io: Some(sfdp::FastRead {
+            opcode: Command::FastReadQuadIo as u8,
+            mode_clocks: 2,
+            dummy_clocks: 4,
+        }),
+    };
+
+    /// Get the fast read instructions described by the SFDP of the chip.
+    pub fn from_sfdp(parameters: &sfdp::BasicFlashParameters) -> Self {
+        Self {
+            dual_output: parameters.fast_read_1_1_2,
+            dual_io: parameters.fast_read_1_2_2,
+            quad_output: parameters.fast_read_1_1_4,
+            quad_io: parameters.fast_read_1_4_4,
+        }
+    }
+}
 
 /// Low level driver for the w25q32jv flash memory chip.
-pub struct W25q32jv<SPI, HOLD, WP> {
+pub struct W25q32jv<SPI, HOLD, WP, DELAY = NoDelay> {
     spi: SPI,
     hold: HOLD,
     wp: WP,
+    geometry: FlashGeometry,
+    erase_opcodes: EraseOpcodes,
+    read_opcodes: ReadOpcodes,
+    four_byte_address_mode: bool,
+    read_mode: ReadMode,
+    quad_enabled: bool,
+    /// The cached WPS bit, `None` until it's read from the chip.
+    individual_block_locks: Option<bool>,
+    /// The cached BP, TB, SEC and CMP bits, `None` until they're read from the chip.
+    block_protection: Option<BlockProtection>,
+    /// The last state the WP pin was set to.
+    wp_state: PinState,
+    qpi_mode: bool,
+    read_parameters: ReadParameters,
+    delay: DELAY,
+    /// The percentage added to the datasheet maximum durations, `None` when timeouts are disabled.
+    timeout_margin: Option<u32>,
+    skip_blank_erases: bool,
+    erase_counters: EraseCounters,
 }
 
-impl<SPI, HOLD, WP> W25q32jv<SPI, HOLD, WP> {
+impl<SPI, HOLD, WP, DELAY> W25q32jv<SPI, HOLD, WP, DELAY> {
+    /// Use the delay to wait between polls of the busy status, and enable the timeouts with [DEFAULT_TIMEOUT_MARGIN].
+    ///
+    /// Without a delay the busy status is polled continuously and the driver waits forever for a chip that stays busy.
+    pub fn with_delay<D>(self, delay: D) -> W25q32jv<SPI, HOLD, WP, D> {
+        W25q32jv {
+            spi: self.spi,
+            hold: self.hold,
+            wp: self.wp,
+            geometry: self.geometry,
+            erase_opcodes: self.erase_opcodes,
+            read_opcodes: self.read_opcodes,
+            four_byte_address_mode: self.four_byte_address_mode,
+            read_mode: self.read_mode,
+            quad_enabled: self.quad_enabled,
+            individual_block_locks: self.individual_block_locks,
+            block_protection: self.block_protection,
+            wp_state: self.wp_state,
+            qpi_mode: self.qpi_mode,
+            read_parameters: self.read_parameters,
+            delay,
+            timeout_margin: Some(DEFAULT_TIMEOUT_MARGIN),
+            skip_blank_erases: self.skip_blank_erases,
+            erase_counters: self.erase_counters,
+        }
+    }
+
+    /// Get the percentage that is added to the datasheet maximum durations before an operation times out.
+    /// `None` when the timeouts are disabled.
+    pub fn timeout_margin(&self) -> Option<u32> {
+        self.timeout_margin
+    }
+
+    /// Set the percentage that is added to the datasheet maximum durations before an operation times out,
+    /// or `None` to disable the timeouts.
+    ///
+    /// The time is measured by adding up the delays, so the timeouts only work with a real delay.
+    pub fn set_timeout_margin(&mut self, timeout_margin: Option<u32>) {
+        self.timeout_margin = timeout_margin;
+    }
+
+    /// The time in µs after which the operation times out, or `None` when the timeouts are disabled.
+    fn timeout_us(&self, operation: BusyOperation) -> Option<u64> {
+        self.timeout_margin
+            .map(|margin| operation.max_duration_us(self.geometry) * (100 + margin as u64) / 100)
+    }
+
+    /// Whether sector and block erases are skipped when the target is already blank.
+    pub fn skip_blank_erases(&self) -> bool {
+        self.skip_blank_erases
+    }
+
+    /// Blank check the target of sector and block erases, including the ones of `erase_range`, and skip the erase
+    /// when it's already all 0xFF. This saves time and wear when the memory is often still blank,
+    /// at the cost of reading the target first. The target is read with the configured [ReadMode].
+    pub fn set_skip_blank_erases(&mut self, skip_blank_erases: bool) {
+        self.skip_blank_erases = skip_blank_erases;
+    }
+
+    /// Get the number of sector and block erases that were done and skipped.
+    pub fn erase_counters(&self) -> EraseCounters {
+        self.erase_counters
+    }
+
+    /// Set the erase counters back to zero.
+    pub fn reset_erase_counters(&mut self) {
+        self.erase_counters = EraseCounters::default();
+    }
+
     /// Get the capacity of the flash chip in bytes.
-    pub fn capacity() -> usize {
-        CAPACITY as usize
+    pub fn capacity(&self) -> usize {
+        self.geometry.capacity() as usize
+    }
+
+    /// Get the geometry of the flash chip the driver is configured for.
+    pub fn geometry(&self) -> FlashGeometry {
+        self.geometry
+    }
+
+    /// Get the instruction the driver uses to read data.
+    pub fn read_mode(&self) -> ReadMode {
+        self.read_mode
+    }
+
+    /// Set the instruction the driver uses to read data. Use [ReadMode::Fast] for SPI clocks above 50 MHz.
+    ///
+    /// The dual and quad read modes need a [transport::Transport] with enough lanes.
+    /// Reads return [Error::Unsupported] when the transport or the chip doesn't support the read mode.
+    pub fn set_read_mode(&mut self, read_mode: ReadMode) {
+        self.read_mode = read_mode;
+    }
+
+    /// Whether the driver has put the chip in QPI mode.
+    pub fn qpi_mode(&self) -> bool {
+        self.qpi_mode
+    }
+
+    /// Get the read parameters that are used in QPI mode.
+    pub fn read_parameters(&self) -> ReadParameters {
+        self.read_parameters
+    }
+
+    /// Get the erase instructions the driver is configured to use.
+    pub fn erase_opcodes(&self) -> EraseOpcodes {
+        self.erase_opcodes
+    }
+
+    /// Set the erase instructions the driver uses, for chips that use different instructions than the Winbond chips.
+    pub fn set_erase_opcodes(&mut self, erase_opcodes: EraseOpcodes) {
+        self.erase_opcodes = erase_opcodes;
+    }
+
+    /// Get the fast read instructions the driver is configured to use.
+    pub fn read_opcodes(&self) -> ReadOpcodes {
+        self.read_opcodes
+    }
+
+    /// Set the fast read instructions the driver uses, for chips that use different instructions than the Winbond chips.
+    pub fn set_read_opcodes(&mut self, read_opcodes: ReadOpcodes) {
+        self.read_opcodes = read_opcodes;
+    }
+
+    /// The largest erase unit the chip supports that starts at the address and fits in the remaining length.
+    fn largest_erase_unit(&self, address: u32, len: u32) -> EraseUnit {
+        [EraseUnit::Block64K, EraseUnit::Block32K]
+            .into_iter()
+            .find(|unit| {
+                address.is_multiple_of(unit.size())
+                    && len >= unit.size()
+                    && self.erase_opcodes.opcode(*unit).is_some()
+            })
+            .unwrap_or(EraseUnit::Sector)
+    }
+
+    /// Create an instruction without address or data, with all phases on four lanes while the chip is in QPI mode.
+    fn instruction(&self, opcode: u8) -> Instruction<'static> {
+        if self.qpi_mode {
+            Instruction::new(opcode).with_lanes(Lanes::Quad, Lanes::Quad, Lanes::Quad)
+        } else {
+            Instruction::new(opcode)
+        }
+    }
+
+    /// Returns [Error::Unsupported] while the chip is in QPI mode, for the instructions that are not available in QPI mode.
+    fn check_not_qpi_mode<S: Debug>(&self) -> Result<(), Error<S>> {
+        if self.qpi_mode {
+            return Err(Error::Unsupported);
+        }
+
+        Ok(())
+    }
+
+    /// The address range of the erase unit with the given index.
+    /// Returns [Error::OutOfBounds] if the unit is not on the chip.
+    fn erase_unit_range<S: Debug>(
+        &self,
+        unit: EraseUnit,
+        index: u32,
+    ) -> Result<Range<u32>, Error<S>> {
+        let address = index.checked_mul(unit.size()).ok_or(Error::OutOfBounds)?;
+        checked_range(address, unit.size() as usize, self.geometry.capacity())
+            .ok_or(Error::OutOfBounds)
+    }
+
+    /// Get the instruction to read data in the configured read mode, without the address and data.
+    /// In QPI mode this is always Fast Read with the dummy clocks of the read parameters.
+    ///
+    /// Returns [Error::Unsupported] when the chip or a transport with `max_lanes` doesn't support the read mode.
+    fn read_instruction<S: Debug>(
+        &self,
+        max_lanes: Lanes,
+    ) -> Result<Instruction<'static>, Error<S>> {
+        if self.qpi_mode {
+            return Ok(self
+                .instruction(Command::FastRead as u8)
+                .with_dummy_cycles(self.read_parameters.dummy_cycles()));
+        }
+
+        let (fast_read, address_lanes, data_lanes) = match self.read_mode {
+            ReadMode::Normal => return Ok(Instruction::new(Command::ReadData as u8)),
+            ReadMode::Fast => {
+                return Ok(Instruction::new(Command::FastRead as u8).with_dummy_cycles(8))
+            }
+            ReadMode::DualOutput => (self.read_opcodes.dual_output, Lanes::Single, Lanes::Dual),
+            ReadMode::DualIo => (self.read_opcodes.dual_io, Lanes::Dual, Lanes::Dual),
+            ReadMode::QuadOutput => (self.read_opcodes.quad_output, Lanes::Single, Lanes::Quad),
+            ReadMode::QuadIo => (self.read_opcodes.quad_io, Lanes::Quad, Lanes::Quad),
+        };
+
+        let fast_read = fast_read.ok_or(Error::Unsupported)?;
+        if data_lanes > max_lanes {
+            return Err(Error::Unsupported);
+        }
+
+        let instruction =
+            Instruction::new(fast_read.opcode).with_lanes(Lanes::Single, address_lanes, data_lanes);
+
+        // Send mode bits that keep the chip out of continuous read mode when they fit in the mode clocks,
+        // otherwise treat the mode clocks as dummy clocks
+        if fast_read.mode_clocks as usize * address_lanes as usize == 8 {
+            Ok(instruction
+                .with_mode_bits(0xFF)
+                .with_dummy_cycles(fast_read.dummy_clocks))
+        } else {
+            Ok(instruction.with_dummy_cycles(fast_read.mode_clocks + fast_read.dummy_clocks))
+        }
+    }
+
+    /// Get the instruction and address width to use for an instruction that takes a memory address.
+    ///
+    /// Chips that need 4-byte addresses use the dedicated 4-byte variant of the instruction,
+    /// unless the chip is in 4-byte address mode and takes 4-byte addresses for all instructions.
+    /// Returns `None` if the chip needs to be put in 4-byte address mode first.
+    fn address_instruction(&self, opcode: u8) -> Option<(u8, AddressWidth)> {
+        match self.geometry.address_width() {
+            AddressWidth::ThreeByte => Some((opcode, AddressWidth::ThreeByte)),
+            AddressWidth::FourByte if self.four_byte_address_mode => {
+                Some((opcode, AddressWidth::FourByte))
+            }
+            AddressWidth::FourByte => {
+                four_byte_opcode(opcode).map(|opcode| (opcode, AddressWidth::FourByte))
+            }
+        }
     }
 }
 
-impl<SPI, S: Debug, P: Debug, HOLD, WP> W25q32jv<SPI, HOLD, WP>
+impl<SPI, S: Debug, HOLD, WP> W25q32jv<SPI, HOLD, WP>
 where
     SPI: embedded_hal::spi::ErrorType<Error = S>,
-    HOLD: OutputPin<Error = P>,
-    WP: OutputPin<Error = P>,
+    HOLD: OptionalPin,
+    WP: OptionalPin,
 {
-    pub fn new(spi: SPI, hold: HOLD, wp: WP) -> Result<Self, Error<S, P>> {
-        let mut flash = W25q32jv { spi, hold, wp };
+    /// Create a new driver for a chip with the given geometry.
+    pub fn new(spi: SPI, hold: HOLD, wp: WP, geometry: FlashGeometry) -> Result<Self, Error<S>> {
+        let mut flash = W25q32jv {
+            spi,
+            hold,
+            wp,
+            geometry,
+            erase_opcodes: EraseOpcodes::WINBOND,
+            read_opcodes: ReadOpcodes::WINBOND,
+            four_byte_address_mode: false,
+            read_mode: ReadMode::Normal,
+            quad_enabled: false,
+            individual_block_locks: None,
+            block_protection: None,
+            wp_state: PinState::High,
+            qpi_mode: false,
+            read_parameters: ReadParameters::default(),
+            delay: NoDelay,
+            timeout_margin: None,
+            skip_blank_erases: false,
+            erase_counters: EraseCounters::default(),
+        };
 
-        flash.hold.set_high().map_err(Error::PinError)?;
-        flash.wp.set_high().map_err(Error::PinError)?;
+        flash.hold.set_inactive().map_err(Error::PinError)?;
+        flash.wp.set_inactive().map_err(Error::PinError)?;
 
         Ok(flash)
     }
+}
 
+impl<SPI, S: Debug, HOLD, WP, DELAY> W25q32jv<SPI, HOLD, WP, DELAY>
+where
+    SPI: embedded_hal::spi::ErrorType<Error = S>,
+    HOLD: OutputPin,
+{
     /// Set the hold pin state.
     ///
     /// The driver doesn't do anything with this pin. When using the chip, make sure the hold pin is not asserted.
     /// By default this means the pin needs to be high (true).
     ///
     /// This function sets the pin directly and can cause the chip to not work.
-    pub fn set_hold(&mut self, value: PinState) -> Result<(), Error<S, P>> {
-        self.hold.set_state(value).map_err(Error::PinError)?;
+    pub fn set_hold(&mut self, value: PinState) -> Result<(), Error<S>> {
+        self.hold
+            .set_state(value)
+            .map_err(|e| Error::PinError(e.kind()))?;
         Ok(())
     }
+}
 
+impl<SPI, S: Debug, HOLD, WP, DELAY> W25q32jv<SPI, HOLD, WP, DELAY>
+where
+    SPI: embedded_hal::spi::ErrorType<Error = S>,
+    WP: OutputPin,
+{
     /// Set the write protect pin state.
     ///
-    /// The driver doesn't do anything with this pin. When using the chip, make sure the hold pin is not asserted.
-    /// By default this means the pin needs to be high (true).
-    ///
-    /// This function sets the pin directly and can cause the chip to not work.
-    pub fn set_wp(&mut self, value: PinState) -> Result<(), Error<S, P>> {
-        self.wp.set_state(value).map_err(Error::PinError)?;
+    /// While the SRP bit is set, a low pin locks the status registers. The driver keeps track of the state
+    /// to return [Error::StatusRegisterLocked] instead of writing to locked status registers.
+    /// Use `enter_hardware_protection` and `exit_hardware_protection` to manage the pin together with the SRP bit.
+    pub fn set_wp(&mut self, value: PinState) -> Result<(), Error<S>> {
+        self.wp
+            .set_state(value)
+            .map_err(|e| Error::PinError(e.kind()))?;
+        self.wp_state = value;
         Ok(())
     }
 }
 
-impl<SPI, S: Debug, P: Debug, HOLD, WP> ErrorType for W25q32jv<SPI, HOLD, WP>
+impl<SPI, S: Debug> W25q32jv<SPI, NoPin, NoPin>
 where
     SPI: embedded_hal::spi::ErrorType<Error = S>,
-    HOLD: OutputPin<Error = P>,
-    WP: OutputPin<Error = P>,
 {
-    type Error = Error<S, P>;
+    /// Create a new driver for a chip with the given geometry, on a board where HOLD and WP are tied high.
+    pub fn new_without_pins(spi: SPI, geometry: FlashGeometry) -> Result<Self, Error<S>> {
+        Self::new(spi, NoPin, NoPin, geometry)
+    }
+}
+
+impl<SPI, S: Debug, HOLD, WP, DELAY> ErrorType for W25q32jv<SPI, HOLD, WP, DELAY>
+where
+    SPI: embedded_hal::spi::ErrorType<Error = S>,
+    HOLD: OptionalPin,
+    WP: OptionalPin,
+{
+    type Error = Error<S>;
+}
+
+/// A HOLD or WP pin of the driver: an [OutputPin], or [NoPin] when the pin is tied high on the board.
+pub trait OptionalPin {
+    /// Drive the pin high, which is the inactive state of HOLD and WP. Does nothing for [NoPin].
+    fn set_inactive(&mut self) -> Result<(), PinErrorKind>;
+}
+
+impl<P: OutputPin> OptionalPin for P {
+    fn set_inactive(&mut self) -> Result<(), PinErrorKind> {
+        self.set_high().map_err(|e| e.kind())
+    }
+}
+
+/// A HOLD or WP pin that is not connected to the MCU, because it's tied high on the board.
+///
+/// It's not an [OutputPin], so `set_hold`, `set_wp` and the hardware write protection,
+/// which need to drive the pin, are not available for it.
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NoPin;
+
+impl OptionalPin for NoPin {
+    fn set_inactive(&mut self) -> Result<(), PinErrorKind> {
+        Ok(())
+    }
+}
+
+/// How the driver waits between polls of the busy status: not at all with [NoDelay], or with a `DelayNs`.
+pub trait PollDelay {
+    /// Wait before the next poll of the busy status.
+    fn wait_us(&mut self, us: u32);
+}
+
+impl<D: embedded_hal::delay::DelayNs> PollDelay for D {
+    fn wait_us(&mut self, us: u32) {
+        self.delay_us(us);
+    }
+}
+
+/// The async version of [PollDelay], for [NoDelay] and every async `DelayNs`.
+#[cfg(feature = "async")]
+#[allow(async_fn_in_trait)]
+pub trait AsyncPollDelay {
+    /// Wait before the next poll of the busy status.
+    async fn wait_us(&mut self, us: u32);
+}
+
+#[cfg(feature = "async")]
+impl<D: embedded_hal_async::delay::DelayNs> AsyncPollDelay for D {
+    async fn wait_us(&mut self, us: u32) {
+        self.delay_us(us).await;
+    }
+}
+
+/// A delay that doesn't wait, used until the driver is given a delay with `with_delay`.
+///
+/// It's not a `DelayNs`, so `suspend`, `resume` and `erase_suspendable_async`, which need to keep
+/// a minimum time between instructions, are only available after `with_delay`.
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NoDelay;
+
+impl PollDelay for NoDelay {
+    fn wait_us(&mut self, _us: u32) {}
+}
+
+#[cfg(feature = "async")]
+impl AsyncPollDelay for NoDelay {
+    async fn wait_us(&mut self, _us: u32) {}
+}
+
+/// An operation during which the chip is busy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum BusyOperation {
+    PageProgram,
+    SectorErase,
+    Block32KErase,
+    Block64KErase,
+    ChipErase,
+    StatusRegisterWrite,
+    Suspend,
+}
+
+impl BusyOperation {
+    /// The maximum duration from the datasheet (tPP, tSE, tBE1, tBE2, tCE, tW and tSUS) in µs.
+    fn max_duration_us(&self, geometry: FlashGeometry) -> u64 {
+        match self {
+            BusyOperation::PageProgram => 3_000,
+            BusyOperation::SectorErase => 400_000,
+            BusyOperation::Block32KErase => 1_600_000,
+            BusyOperation::Block64KErase => 2_000_000,
+            // 50 s for the 4 MiB W25Q32, scaling with the capacity
+            BusyOperation::ChipErase => 12_500_000 * geometry.capacity().div_ceil(1 << 20) as u64,
+            BusyOperation::StatusRegisterWrite => 15_000,
+            BusyOperation::Suspend => 20,
+        }
+    }
+
+    /// The interval at which the busy status is polled in µs, a fraction of the typical duration.
+    fn poll_interval_us(&self) -> u32 {
+        match self {
+            BusyOperation::PageProgram => 50,
+            BusyOperation::SectorErase => 1_000,
+            BusyOperation::Block32KErase | BusyOperation::Block64KErase => 5_000,
+            BusyOperation::ChipErase => 100_000,
+            BusyOperation::StatusRegisterWrite => 1_000,
+            BusyOperation::Suspend => 5,
+        }
+    }
+}
+
+/// Identification of the chip as returned by the JEDEC ID (0x9F) command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct JedecId {
+    /// The manufacturer ID. This is [WINBOND_MANUFACTURER_ID] for Winbond chips.
+    pub manufacturer: u8,
+    /// The memory type, e.g. 0x40 or 0x70 for the W25Q32JV depending on the variant.
+    pub memory_type: u8,
+    /// The capacity code, e.g. 0x16 for the 32 M-bit W25Q32 and 0x20 for the 512 M-bit W25Q512.
+    pub capacity: u8,
+}
+
+impl JedecId {
+    /// Get the capacity in bytes that is encoded in the ID.
+    /// Returns `None` if the capacity code is not one of [WINBOND_CAPACITY_CODES].
+    pub const fn capacity_bytes(&self) -> Option<u32> {
+        let mut i = 0;
+        while i < WINBOND_CAPACITY_CODES.len() {
+            let (code, geometry) = WINBOND_CAPACITY_CODES[i];
+            if code == self.capacity {
+                return Some(geometry.capacity());
+            }
+            i += 1;
+        }
+
+        None
+    }
+}
+
+/// The capacity codes in the JEDEC ID of the supported Winbond chips.
+///
+/// Up to 256 M-bit the code is log2 of the capacity in bytes, but the 512 M-bit W25Q512 reports 0x20.
+pub const WINBOND_CAPACITY_CODES: [(u8, FlashGeometry); 5] = [
+    (0x16, FlashGeometry::W25Q32),
+    (0x17, FlashGeometry::W25Q64),
+    (0x18, FlashGeometry::W25Q128),
+    (0x19, FlashGeometry::W25Q256),
+    (0x20, FlashGeometry::W25Q512),
+];
+
+/// Identification of the chip as returned by the Manufacturer/Device ID (0x90) command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ManufacturerDeviceId {
+    /// The manufacturer ID. This is [WINBOND_MANUFACTURER_ID] for Winbond chips.
+    pub manufacturer: u8,
+    /// The device ID, e.g. 0x15 for the W25Q32JV.
+    pub device: u8,
 }
 
 /// Custom error type for the various errors that can be thrown by W25q32jv.
@@ -96,16 +946,31 @@ where
 #[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[non_exhaustive]
-pub enum Error<S: Debug, P: Debug> {
+pub enum Error<S: Debug> {
     SpiError(S),
-    PinError(P),
+    /// Setting the HOLD or WP pin failed.
+    PinError(PinErrorKind),
     NotAligned,
     OutOfBounds,
     WriteEnableFail,
     ReadbackFail,
+    /// The chip on the bus is not the expected chip. Contains the JEDEC ID that was read.
+    UnexpectedChip(JedecId),
+    /// The SFDP data of the chip is missing or invalid.
+    InvalidSfdp,
+    /// The chip or the transport doesn't support the requested operation.
+    Unsupported,
+    /// The requested range can't be protected with the block protection bits of the chip.
+    InvalidProtectionRange,
+    /// The target of a write or erase is protected by the block protection bits or its individual block lock.
+    WriteProtected,
+    /// The status registers are locked by the SRP and SRL bits and the WP pin.
+    StatusRegisterLocked,
+    /// The chip didn't finish an erase, program or status register write within the datasheet maximum plus the timeout margin.
+    Timeout,
 }
 
-impl<S: Debug, P: Debug> NorFlashError for Error<S, P> {
+impl<S: Debug> NorFlashError for Error<S> {
     fn kind(&self) -> NorFlashErrorKind {
         match self {
             Error::NotAligned => NorFlashErrorKind::NotAligned,
@@ -115,30 +980,138 @@ impl<S: Debug, P: Debug> NorFlashError for Error<S, P> {
     }
 }
 
+/// The error of a range erase, together with the progress that was made before the error.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EraseRangeError<S: Debug> {
+    /// The error that stopped the erase.
+    pub error: Error<S>,
+    /// The address of the first sector of the erase that failed, or the bound of the range that is not aligned.
+    pub address: u32,
+    /// The number of sectors at the start of the range that were erased before the error.
+    pub erased_sectors: u32,
+}
+
+impl<S: Debug> EraseRangeError<S> {
+    fn new(error: Error<S>, start_address: u32, address: u32) -> Self {
+        Self {
+            error,
+            address,
+            erased_sectors: (address - start_address) / SECTOR_SIZE,
+        }
+    }
+
+    /// The error of a range that was rejected before anything was erased.
+    fn rejected(error: Error<S>, address: u32) -> Self {
+        Self {
+            error,
+            address,
+            erased_sectors: 0,
+        }
+    }
+}
+
+impl<S: Debug> From<EraseRangeError<S>> for Error<S> {
+    fn from(error: EraseRangeError<S>) -> Self {
+        error.error
+    }
+}
+
+impl<S: Debug> NorFlashError for EraseRangeError<S> {
+    fn kind(&self) -> NorFlashErrorKind {
+        self.error.kind()
+    }
+}
+
 /// Easily readable representation of the command bytes used by the flash chip.
+#[derive(Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 enum Command {
     PageProgram = 0x02,
+    QuadPageProgram = 0x32,
     ReadData = 0x03,
+    FastRead = 0x0B,
+    FastReadDualOutput = 0x3B,
+    FastReadDualIo = 0xBB,
+    FastReadQuadOutput = 0x6B,
+    FastReadQuadIo = 0xEB,
     ReadStatusRegister1 = 0x05,
+    ReadStatusRegister2 = 0x35,
+    ReadStatusRegister3 = 0x15,
+    WriteStatusRegister1 = 0x01,
+    WriteStatusRegister2 = 0x31,
+    WriteStatusRegister3 = 0x11,
+    VolatileStatusRegisterWriteEnable = 0x50,
     WriteEnable = 0x06,
     SectorErase = 0x20,
     UniqueId = 0x4B,
+    ReadSfdp = 0x5A,
+    ManufacturerDeviceId = 0x90,
+    JedecId = 0x9F,
     Block32Erase = 0x52,
     Block64Erase = 0xD8,
+    ReadData4 = 0x13,
+    FastRead4 = 0x0C,
+    FastReadDualOutput4 = 0x3C,
+    FastReadDualIo4 = 0xBC,
+    FastReadQuadOutput4 = 0x6C,
+    FastReadQuadIo4 = 0xEC,
+    PageProgram4 = 0x12,
+    QuadPageProgram4 = 0x34,
+    SectorErase4 = 0x21,
+    Block64Erase4 = 0xDC,
+    Enter4ByteAddressMode = 0xB7,
+    Exit4ByteAddressMode = 0xE9,
     ChipErase = 0xC7,
     EnableReset = 0x66,
     PowerDown = 0xB9,
     ReleasePowerDown = 0xAB,
     Reset = 0x99,
+    EnterQpiMode = 0x38,
+    ExitQpiMode = 0xFF,
+    SetReadParameters = 0xC0,
+    IndividualBlockLock = 0x36,
+    IndividualBlockUnlock = 0x39,
+    ReadBlockLock = 0x3D,
+    GlobalBlockLock = 0x7E,
+    GlobalBlockUnlock = 0x98,
+    ReadSecurityRegister = 0x48,
+    ProgramSecurityRegister = 0x42,
+    EraseProgramSuspend = 0x75,
+    EraseProgramResume = 0x7A,
+    EraseSecurityRegister = 0x44,
 }
 
-fn command_and_address(command: u8, address: u32) -> [u8; 4] {
-    [
-        command,
-        // MSB, BE
-        ((address & 0xFF0000) >> 16) as u8,
-        ((address & 0x00FF00) >> 8) as u8,
-        ((address & 0x0000FF) >> 0) as u8,
-    ]
+/// The range of `len` bytes starting at `address`, if it fits in `size` bytes.
+fn checked_range(address: u32, len: usize, size: u32) -> Option<Range<u32>> {
+    let end = address.checked_add(u32::try_from(len).ok()?)?;
+    (end <= size).then_some(address..end)
+}
+
+/// Get the dedicated 4-byte address variant of an instruction, if there is one.
+fn four_byte_opcode(opcode: u8) -> Option<u8> {
+    const READ_DATA: u8 = Command::ReadData as u8;
+    const FAST_READ: u8 = Command::FastRead as u8;
+    const FAST_READ_DUAL_OUTPUT: u8 = Command::FastReadDualOutput as u8;
+    const FAST_READ_DUAL_IO: u8 = Command::FastReadDualIo as u8;
+    const FAST_READ_QUAD_OUTPUT: u8 = Command::FastReadQuadOutput as u8;
+    const FAST_READ_QUAD_IO: u8 = Command::FastReadQuadIo as u8;
+    const PAGE_PROGRAM: u8 = Command::PageProgram as u8;
+    const QUAD_PAGE_PROGRAM: u8 = Command::QuadPageProgram as u8;
+    const SECTOR_ERASE: u8 = Command::SectorErase as u8;
+    const BLOCK_64_ERASE: u8 = Command::Block64Erase as u8;
+
+    match opcode {
+        READ_DATA => Some(Command::ReadData4 as u8),
+        FAST_READ => Some(Command::FastRead4 as u8),
+        FAST_READ_DUAL_OUTPUT => Some(Command::FastReadDualOutput4 as u8),
+        FAST_READ_DUAL_IO => Some(Command::FastReadDualIo4 as u8),
+        FAST_READ_QUAD_OUTPUT => Some(Command::FastReadQuadOutput4 as u8),
+        FAST_READ_QUAD_IO => Some(Command::FastReadQuadIo4 as u8),
+        PAGE_PROGRAM => Some(Command::PageProgram4 as u8),
+        QUAD_PAGE_PROGRAM => Some(Command::QuadPageProgram4 as u8),
+        SECTOR_ERASE => Some(Command::SectorErase4 as u8),
+        BLOCK_64_ERASE => Some(Command::Block64Erase4 as u8),
+        _ => None,
+    }
 }
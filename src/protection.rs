@@ -0,0 +1,80 @@
+//! Block protection with the BP, TB, SEC and CMP bits of the status registers.
+//!
+//! The protected area is always a contiguous range at the top or the bottom of the memory,
+//! or the complement of such a range. Only the layout of the 32 to 128 M-bit chips is supported.
+
+use crate::{FlashGeometry, BLOCK_32K_SIZE, SECTOR_SIZE};
+use core::ops::Range;
+
+/// A combination of the block protection bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BlockProtection {
+    /// BP2-BP0 in status register 1.
+    pub bp: u8,
+    /// TB in status register 1.
+    pub tb: bool,
+    /// SEC in status register 1.
+    pub sec: bool,
+    /// CMP in status register 2.
+    pub cmp: bool,
+}
+
+impl BlockProtection {
+    /// Nothing is protected.
+    pub const NONE: Self = Self {
+        bp: 0,
+        tb: false,
+        sec: false,
+        cmp: false,
+    };
+
+    /// Whether the block protection bits of the chip with the given geometry are supported.
+    pub fn is_supported(geometry: FlashGeometry) -> bool {
+        matches!(
+            geometry,
+            FlashGeometry::W25Q32 | FlashGeometry::W25Q64 | FlashGeometry::W25Q128
+        )
+    }
+
+    /// The address range that is protected by these bits. An empty range means nothing is protected.
+    ///
+    /// Returns `None` if the geometry is not supported.
+    pub fn protected_range(&self, geometry: FlashGeometry) -> Option<Range<u32>> {
+        if !Self::is_supported(geometry) {
+            return None;
+        }
+
+        let capacity = geometry.capacity();
+        let size = match (self.bp & 0x07, self.sec) {
+            (0, _) => 0,
+            (7, _) => capacity,
+            // The smallest protected area is 1/64th of the memory
+            (bp, false) => (capacity / 64) << (bp - 1),
+            (bp, true) => (SECTOR_SIZE << (bp - 1)).min(BLOCK_32K_SIZE),
+        };
+
+        let range = match (self.tb, self.cmp) {
+            (false, false) => capacity - size..capacity,
+            (true, false) => 0..size,
+            (false, true) => 0..capacity - size,
+            (true, true) => size..capacity,
+        };
+
+        Some(if range.is_empty() { 0..0 } else { range })
+    }
+
+    /// Find the bits that protect exactly the given range. Prefers combinations without CMP and SEC.
+    ///
+    /// Returns `None` if the range can't be represented or the geometry is not supported.
+    pub fn from_range(range: Range<u32>, geometry: FlashGeometry) -> Option<Self> {
+        let range = if range.is_empty() { 0..0 } else { range };
+
+        [false, true]
+            .into_iter()
+            .flat_map(|cmp| [false, true].into_iter().map(move |sec| (cmp, sec)))
+            .flat_map(|(cmp, sec)| [false, true].into_iter().map(move |tb| (cmp, sec, tb)))
+            .flat_map(|(cmp, sec, tb)| (0..8).map(move |bp| Self { bp, tb, sec, cmp }))
+            .find(|bits| bits.protected_range(geometry) == Some(range.clone()))
+    }
+}
@@ -0,0 +1,481 @@
+//! Parsing of the Serial Flash Discoverable Parameters (SFDP, JESD216) of the chip.
+//!
+//! The SFDP data can be read with the Read SFDP (0x5A) command and describes the density,
+//! the supported erase types and the supported fast read modes of the chip.
+
+/// The "SFDP" signature at the start of the SFDP header, read as a little endian word.
+pub const SFDP_SIGNATURE: u32 = 0x5044_4653;
+
+/// The ID of the JEDEC Basic Flash Parameter Table.
+pub const BASIC_FLASH_PARAMETER_TABLE_ID: u16 = 0xFF00;
+
+/// The size of the SFDP header and of each parameter header in bytes.
+pub const HEADER_SIZE: usize = 8;
+
+/// The maximum number of DWORDs of the Basic Flash Parameter Table that are read and parsed.
+pub const MAX_BASIC_FLASH_PARAMETER_DWORDS: usize = 16;
+
+/// The minimum number of DWORDs of the Basic Flash Parameter Table, as defined by the original JESD216.
+pub const MIN_BASIC_FLASH_PARAMETER_DWORDS: usize = 9;
+
+/// The SFDP header that lives at SFDP address 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SfdpHeader {
+    pub minor_revision: u8,
+    pub major_revision: u8,
+    /// The number of parameter headers that follow the SFDP header.
+    pub n_parameter_headers: u16,
+    pub access_protocol: u8,
+}
+
+impl SfdpHeader {
+    /// Parse the SFDP header. Returns `None` if the signature doesn't match.
+    pub fn parse(bytes: &[u8; HEADER_SIZE]) -> Option<Self> {
+        if u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) != SFDP_SIGNATURE {
+            return None;
+        }
+
+        Some(Self {
+            minor_revision: bytes[4],
+            major_revision: bytes[5],
+            // The field holds the number of parameter headers minus one
+            n_parameter_headers: u16::from(bytes[6]) + 1,
+            access_protocol: bytes[7],
+        })
+    }
+
+    /// The SFDP address of the parameter header with the given index.
+    pub fn parameter_header_address(index: u16) -> u32 {
+        (HEADER_SIZE * (1 + index as usize)) as u32
+    }
+}
+
+/// A parameter header that points to a parameter table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ParameterHeader {
+    /// The ID of the parameter table, e.g. [BASIC_FLASH_PARAMETER_TABLE_ID].
+    pub id: u16,
+    pub minor_revision: u8,
+    pub major_revision: u8,
+    /// The length of the parameter table in DWORDs.
+    pub length: u8,
+    /// The SFDP address of the parameter table.
+    pub table_pointer: u32,
+}
+
+impl ParameterHeader {
+    /// Whether this header describes a newer Basic Flash Parameter Table than the given one.
+    pub fn is_newer_basic_table(&self, current: Option<&Self>) -> bool {
+        self.id == BASIC_FLASH_PARAMETER_TABLE_ID
+            && current.is_none_or(|current| {
+                (self.major_revision, self.minor_revision)
+                    > (current.major_revision, current.minor_revision)
+            })
+    }
+
+    /// The number of bytes of the table that are read and parsed.
+    pub fn read_length(&self) -> usize {
+        (self.length as usize).min(MAX_BASIC_FLASH_PARAMETER_DWORDS) * 4
+    }
+
+    /// Parse a parameter header.
+    pub fn parse(bytes: &[u8; HEADER_SIZE]) -> Self {
+        Self {
+            id: u16::from_le_bytes([bytes[0], bytes[7]]),
+            minor_revision: bytes[1],
+            major_revision: bytes[2],
+            length: bytes[3],
+            table_pointer: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], 0]),
+        }
+    }
+}
+
+/// The address modes the chip supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AddressBytes {
+    ThreeByteOnly,
+    ThreeOrFourByte,
+    FourByteOnly,
+}
+
+/// An erase type described by the Basic Flash Parameter Table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EraseType {
+    /// The number of bytes that are erased.
+    pub size: u32,
+    pub opcode: u8,
+}
+
+/// The instruction and timing of a fast read mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FastRead {
+    pub opcode: u8,
+    /// The number of clocks used to send the mode bits after the address.
+    pub mode_clocks: u8,
+    /// The number of dummy clocks after the mode bits.
+    pub dummy_clocks: u8,
+}
+
+impl FastRead {
+    fn parse(bits: u16) -> Self {
+        Self {
+            opcode: (bits >> 8) as u8,
+            mode_clocks: ((bits >> 5) & 0x07) as u8,
+            dummy_clocks: (bits & 0x1F) as u8,
+        }
+    }
+}
+
+/// The parsed JEDEC Basic Flash Parameter Table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BasicFlashParameters {
+    /// The capacity of the chip in bytes.
+    pub capacity: u64,
+    pub address_bytes: AddressBytes,
+    /// The instruction to erase 4 KiB, if uniform 4 KiB erases are supported.
+    pub erase_4k_opcode: Option<u8>,
+    /// Up to four supported erase types.
+    pub erase_types: [Option<EraseType>; 4],
+    pub fast_read_1_1_2: Option<FastRead>,
+    pub fast_read_1_2_2: Option<FastRead>,
+    pub fast_read_1_1_4: Option<FastRead>,
+    pub fast_read_1_4_4: Option<FastRead>,
+    pub fast_read_2_2_2: Option<FastRead>,
+    pub fast_read_4_4_4: Option<FastRead>,
+    /// The page size in bytes. Only present in the tables of JESD216A and later.
+    pub page_size: Option<u32>,
+}
+
+impl BasicFlashParameters {
+    /// Parse the Basic Flash Parameter Table from its DWORDs.
+    ///
+    /// Returns `None` if the table is shorter than [MIN_BASIC_FLASH_PARAMETER_DWORDS] or the density is invalid.
+    pub fn parse(dwords: &[u32]) -> Option<Self> {
+        if dwords.len() < MIN_BASIC_FLASH_PARAMETER_DWORDS {
+            return None;
+        }
+
+        let dword = |index: usize| dwords[index - 1];
+        let bit = |index: usize, bit: u32| dword(index) & (1 << bit) != 0;
+        let fast_read =
+            |supported: bool, bits: u32| supported.then(|| FastRead::parse(bits as u16));
+        let erase_type = |bits: u32| {
+            let size = bits & 0xFF;
+            (size != 0 && size < 32).then(|| EraseType {
+                size: 1 << size,
+                opcode: (bits >> 8) as u8,
+            })
+        };
+
+        let density = dword(2);
+        let capacity = if density & 0x8000_0000 == 0 {
+            (density as u64 + 1) / 8
+        } else {
+            1u64.checked_shl(density & 0x7FFF_FFFF)? / 8
+        };
+
+        let address_bytes = match (dword(1) >> 17) & 0x03 {
+            0b00 => AddressBytes::ThreeByteOnly,
+            0b01 => AddressBytes::ThreeOrFourByte,
+            0b10 => AddressBytes::FourByteOnly,
+            _ => return None,
+        };
+
+        let page_size = dwords.get(10).map(|dword_11| 1 << ((dword_11 >> 4) & 0x0F));
+
+        Some(Self {
+            capacity,
+            address_bytes,
+            erase_4k_opcode: (dword(1) & 0x03 == 0b01).then(|| (dword(1) >> 8) as u8),
+            erase_types: [
+                erase_type(dword(8)),
+                erase_type(dword(8) >> 16),
+                erase_type(dword(9)),
+                erase_type(dword(9) >> 16),
+            ],
+            fast_read_1_1_2: fast_read(bit(1, 16), dword(4)),
+            fast_read_1_2_2: fast_read(bit(1, 20), dword(4) >> 16),
+            fast_read_1_1_4: fast_read(bit(1, 22), dword(3) >> 16),
+            fast_read_1_4_4: fast_read(bit(1, 21), dword(3)),
+            fast_read_2_2_2: fast_read(bit(5, 0), dword(6) >> 16),
+            fast_read_4_4_4: fast_read(bit(5, 4), dword(7) >> 16),
+            page_size,
+        })
+    }
+
+    /// Parse the Basic Flash Parameter Table from its little endian bytes, as read from the chip.
+    pub fn parse_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut dwords = [0; MAX_BASIC_FLASH_PARAMETER_DWORDS];
+        let mut length = 0;
+
+        for (dword, bytes) in dwords.iter_mut().zip(bytes.as_chunks::<4>().0) {
+            *dword = u32::from_le_bytes(*bytes);
+            length += 1;
+        }
+
+        Self::parse(&dwords[..length])
+    }
+
+    /// Get the instruction that erases the given number of bytes, if the chip supports it.
+    pub fn erase_opcode(&self, size: u32) -> Option<u8> {
+        self.erase_types
+            .iter()
+            .flatten()
+            .find(|erase_type| erase_type.size == size)
+            .map(|erase_type| erase_type.opcode)
+            .or(self.erase_4k_opcode.filter(|_| size == 4096))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SFDP header, parameter header and Basic Flash Parameter Table of a W25Q32JV.
+    const W25Q32JV_HEADER: [u8; 16] = [
+        // SFDP header
+        0x53, 0x46, 0x44, 0x50, 0x05, 0x01, 0x00, 0xFF,
+        // Basic Flash Parameter Table header
+        0x00, 0x05, 0x01, 0x10, 0x80, 0x00, 0x00, 0xFF,
+    ];
+    const W25Q32JV_BASIC_TABLE: [u8; 64] = [
+        0xE5, 0x20, 0xF9, 0xFF, 0xFF, 0xFF, 0xFF, 0x01, 0x44, 0xEB, 0x08, 0x6B, 0x08, 0x3B, 0x42,
+        0xBB, 0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0xFF, 0xFF, 0x40, 0xEB, 0x0C, 0x20,
+        0x0F, 0x52, 0x10, 0xD8, 0x00, 0x00, 0x36, 0x02, 0xA6, 0x00, 0x82, 0xEA, 0x14, 0xC9, 0xE9,
+        0x63, 0x76, 0x33, 0x7A, 0x75, 0x7A, 0x75, 0xF7, 0xA2, 0xD5, 0x5C, 0x19, 0xF7, 0x4D, 0xFF,
+        0xE9, 0x30, 0xF8, 0x80,
+    ];
+
+    /// The SFDP header, parameter headers and Basic Flash Parameter Table of a W25Q256JV.
+    const W25Q256JV_HEADER: [u8; 24] = [
+        // SFDP header
+        0x53, 0x46, 0x44, 0x50, 0x06, 0x01, 0x01, 0xFF,
+        // Basic Flash Parameter Table header
+        0x00, 0x06, 0x01, 0x10, 0x80, 0x00, 0x00, 0xFF,
+        // 4-Byte Address Instruction Table header
+        0x84, 0x00, 0x01, 0x02, 0xD0, 0x00, 0x00, 0xFF,
+    ];
+    const W25Q256JV_BASIC_TABLE: [u8; 64] = [
+        0xE5, 0x20, 0xFB, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F, 0x44, 0xEB, 0x08, 0x6B, 0x08, 0x3B, 0x42,
+        0xBB, 0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0xFF, 0xFF, 0x40, 0xEB, 0x0C, 0x20,
+        0x0F, 0x52, 0x10, 0xD8, 0x00, 0x00, 0x36, 0x02, 0xA6, 0x00, 0x82, 0xEA, 0x14, 0xC9, 0xE9,
+        0x63, 0x76, 0x33, 0x7A, 0x75, 0x7A, 0x75, 0xF7, 0xA2, 0xD5, 0x5C, 0x19, 0xF7, 0x4D, 0xFF,
+        0xE9, 0x30, 0xF8, 0x80,
+    ];
+
+    fn header(bytes: &[u8], index: usize) -> &[u8; HEADER_SIZE] {
+        bytes[index * HEADER_SIZE..][..HEADER_SIZE]
+            .try_into()
+            .unwrap()
+    }
+
+    fn dwords(bytes: &[u8]) -> [u32; MAX_BASIC_FLASH_PARAMETER_DWORDS] {
+        let mut dwords = [0; MAX_BASIC_FLASH_PARAMETER_DWORDS];
+        for (dword, bytes) in dwords.iter_mut().zip(bytes.as_chunks::<4>().0) {
+            *dword = u32::from_le_bytes(*bytes);
+        }
+        dwords
+    }
+
+    #[test]
+    fn w25q32jv_headers() {
+        let sfdp_header = SfdpHeader::parse(header(&W25Q32JV_HEADER, 0)).unwrap();
+        assert_eq!(
+            sfdp_header,
+            SfdpHeader {
+                minor_revision: 5,
+                major_revision: 1,
+                n_parameter_headers: 1,
+                access_protocol: 0xFF,
+            }
+        );
+
+        let parameter_header = ParameterHeader::parse(header(&W25Q32JV_HEADER, 1));
+        assert_eq!(
+            parameter_header,
+            ParameterHeader {
+                id: BASIC_FLASH_PARAMETER_TABLE_ID,
+                minor_revision: 5,
+                major_revision: 1,
+                length: 16,
+                table_pointer: 0x80,
+            }
+        );
+        assert!(parameter_header.is_newer_basic_table(None));
+        assert_eq!(parameter_header.read_length(), 64);
+    }
+
+    #[test]
+    fn w25q32jv_basic_table() {
+        let parameters = BasicFlashParameters::parse_bytes(&W25Q32JV_BASIC_TABLE).unwrap();
+
+        assert_eq!(
+            parameters,
+            BasicFlashParameters {
+                capacity: 4 * 1024 * 1024,
+                address_bytes: AddressBytes::ThreeByteOnly,
+                erase_4k_opcode: Some(0x20),
+                erase_types: [
+                    Some(EraseType {
+                        size: 4096,
+                        opcode: 0x20
+                    }),
+                    Some(EraseType {
+                        size: 32768,
+                        opcode: 0x52
+                    }),
+                    Some(EraseType {
+                        size: 65536,
+                        opcode: 0xD8
+                    }),
+                    None,
+                ],
+                fast_read_1_1_2: Some(FastRead {
+                    opcode: 0x3B,
+                    mode_clocks: 0,
+                    dummy_clocks: 8
+                }),
+                fast_read_1_2_2: Some(FastRead {
+                    opcode: 0xBB,
+                    mode_clocks: 2,
+                    dummy_clocks: 2
+                }),
+                fast_read_1_1_4: Some(FastRead {
+                    opcode: 0x6B,
+                    mode_clocks: 0,
+                    dummy_clocks: 8
+                }),
+                fast_read_1_4_4: Some(FastRead {
+                    opcode: 0xEB,
+                    mode_clocks: 2,
+                    dummy_clocks: 4
+                }),
+                fast_read_2_2_2: None,
+                fast_read_4_4_4: Some(FastRead {
+                    opcode: 0xEB,
+                    mode_clocks: 2,
+                    dummy_clocks: 0
+                }),
+                page_size: Some(256),
+            }
+        );
+        assert_eq!(parameters.erase_opcode(32768), Some(0x52));
+        assert_eq!(parameters.erase_opcode(65536), Some(0xD8));
+        assert_eq!(parameters.erase_opcode(8192), None);
+    }
+
+    #[test]
+    fn w25q256jv_headers() {
+        let sfdp_header = SfdpHeader::parse(header(&W25Q256JV_HEADER, 0)).unwrap();
+        assert_eq!(sfdp_header.n_parameter_headers, 2);
+        assert_eq!(SfdpHeader::parameter_header_address(1), 16);
+
+        let basic_table = ParameterHeader::parse(header(&W25Q256JV_HEADER, 1));
+        let four_byte_table = ParameterHeader::parse(header(&W25Q256JV_HEADER, 2));
+        assert_eq!(four_byte_table.id, 0xFF84);
+        assert_eq!(four_byte_table.table_pointer, 0xD0);
+        assert!(basic_table.is_newer_basic_table(None));
+        assert!(!four_byte_table.is_newer_basic_table(Some(&basic_table)));
+    }
+
+    #[test]
+    fn w25q256jv_basic_table() {
+        let parameters = BasicFlashParameters::parse_bytes(&W25Q256JV_BASIC_TABLE).unwrap();
+
+        assert_eq!(parameters.capacity, 32 * 1024 * 1024);
+        assert_eq!(parameters.address_bytes, AddressBytes::ThreeOrFourByte);
+        assert_eq!(parameters.erase_opcode(4096), Some(0x20));
+        assert_eq!(parameters.erase_opcode(65536), Some(0xD8));
+        assert_eq!(parameters.page_size, Some(256));
+    }
+
+    #[test]
+    fn invalid_signature() {
+        let mut bytes = *header(&W25Q32JV_HEADER, 0);
+        bytes[0] = 0xFF;
+        assert_eq!(SfdpHeader::parse(&bytes), None);
+    }
+
+    #[test]
+    fn power_of_two_density() {
+        let mut dwords = dwords(&W25Q32JV_BASIC_TABLE);
+
+        // Bit 31 set: the density is 2^N bits
+        dwords[1] = 0x8000_0021;
+        let parameters = BasicFlashParameters::parse(&dwords).unwrap();
+        assert_eq!(parameters.capacity, 1 << 30);
+
+        dwords[1] = 0x8000_0040;
+        assert_eq!(BasicFlashParameters::parse(&dwords), None);
+    }
+
+    #[test]
+    fn invalid_address_bytes() {
+        let mut dwords = dwords(&W25Q32JV_BASIC_TABLE);
+        dwords[0] |= 0b11 << 17;
+        assert_eq!(BasicFlashParameters::parse(&dwords), None);
+    }
+
+    #[test]
+    fn erase_types() {
+        let mut dwords = dwords(&W25Q32JV_BASIC_TABLE);
+
+        // No uniform 4 KiB erase, erase type 1 is unused and erase type 4 erases 256 KiB
+        dwords[0] = (dwords[0] & !0x03) | 0b11;
+        dwords[7] &= 0xFFFF_0000;
+        dwords[8] = 0xDC12_D810;
+        let parameters = BasicFlashParameters::parse(&dwords).unwrap();
+
+        assert_eq!(parameters.erase_4k_opcode, None);
+        assert_eq!(
+            parameters.erase_types,
+            [
+                None,
+                Some(EraseType {
+                    size: 32768,
+                    opcode: 0x52
+                }),
+                Some(EraseType {
+                    size: 65536,
+                    opcode: 0xD8
+                }),
+                Some(EraseType {
+                    size: 262144,
+                    opcode: 0xDC
+                }),
+            ]
+        );
+        assert_eq!(parameters.erase_opcode(4096), None);
+        assert_eq!(parameters.erase_opcode(262144), Some(0xDC));
+    }
+
+    #[test]
+    fn jesd216_table_without_page_size() {
+        // The original JESD216 table ends after DWORD 9
+        let parameters = BasicFlashParameters::parse_bytes(&W25Q32JV_BASIC_TABLE[..36]).unwrap();
+
+        assert_eq!(parameters.capacity, 4 * 1024 * 1024);
+        assert_eq!(parameters.page_size, None);
+        assert_eq!(parameters.erase_opcode(65536), Some(0xD8));
+    }
+
+    #[test]
+    fn too_short_table() {
+        assert_eq!(
+            BasicFlashParameters::parse_bytes(&W25Q32JV_BASIC_TABLE[..32]),
+            None
+        );
+        // A partial DWORD is not counted
+        assert_eq!(
+            BasicFlashParameters::parse_bytes(&W25Q32JV_BASIC_TABLE[..35]),
+            None
+        );
+        assert_eq!(BasicFlashParameters::parse(&[]), None);
+    }
+}
@@ -0,0 +1,888 @@
+//! A command-level simulation of a W25Q chip, to test the driver and code built on it on a host.
+//!
+//! [SimChip] implements the blocking and async `SpiDevice` traits. It decodes the instructions byte by byte,
+//! the way a single-lane transport sends them, and models the behaviour of the chip that the driver relies on:
+//! - Programs can only clear bits and wrap around within the page, erases set all bits of the unit to 1.
+//! - Programs, erases and status register writes need the write enable latch and clear it.
+//! - The BUSY bit is set after each program, erase and non-volatile status register write, for a configurable
+//!   number of status register reads or for the duration of the operation on a [clock::VirtualClock].
+//!   Only status reads and suspend are accepted while busy.
+//! - Power-down, software reset, 4-byte address mode with the ADP power-up default, suspend and resume.
+//! - The block protection bits, the individual block locks and the security registers with their lock bits.
+//!
+//! The WP and HOLD pins are not simulated, the chip behaves as if they're high.
+//! Quad instructions are decoded as if their address, mode bits and dummy cycles were sent on a single lane.
+//! In QPI mode only Exit QPI Mode (0xFF) is recognized, because a single-lane bus can't send QPI instructions.
+//!
+//! [SimChip] is a handle to shared state, so a clone can be kept to inspect the memory
+//! while the driver owns the other one. Wrap it in a [fault::FaultyChip] to simulate power loss,
+//! and in a [trace::Recorder] or [trace::Replayer] to check the transactions of the driver.
+
+use crate::protection::BlockProtection;
+use crate::status::{StatusRegister1, StatusRegister2, StatusRegister3};
+use crate::{
+    AddressWidth, BusyOperation, Command, EraseUnit, FlashGeometry, SecurityRegister, PAGE_SIZE,
+    SECTOR_SIZE, SECURITY_REGISTER_SIZE, WINBOND_CAPACITY_CODES, WINBOND_MANUFACTURER_ID,
+};
+use clock::{Timing, VirtualClock};
+use core::convert::Infallible;
+use core::ops::Range;
+use embedded_hal::spi::{ErrorType, Operation};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::vec;
+use std::vec::Vec;
+
+pub mod clock;
+pub mod fault;
+pub mod trace;
+
+/// The memory type byte of the JEDEC ID of the W25QxxJV-IQ chips.
+pub const MEMORY_TYPE: u8 = 0x40;
+
+/// The unique ID that is returned by Read Unique ID (0x4B).
+pub const UNIQUE_ID: [u8; 8] = [0xD2, 0x63, 0x1C, 0x8A, 0x47, 0x11, 0x2B, 0x5E];
+
+/// The default number of status register reads that report BUSY after an operation.
+pub const DEFAULT_BUSY_POLLS: u32 = 1;
+
+/// The ADP bit of status register 3 of the 4-byte address chips, which makes them start in 4-byte address mode.
+const ADP: u8 = 0x02;
+
+/// A simulated W25Q chip.
+#[derive(Clone)]
+pub struct SimChip {
+    chip: Arc<Mutex<Chip>>,
+}
+
+impl SimChip {
+    /// Create an erased chip with the given geometry, as it is after power-up.
+    pub fn new(geometry: FlashGeometry) -> Self {
+        Self {
+            chip: Arc::new(Mutex::new(Chip::new(geometry))),
+        }
+    }
+
+    /// Let operations take the given time on the clock, instead of a number of status register reads.
+    pub fn with_clock(self, clock: VirtualClock, timing: Timing) -> Self {
+        self.chip().clock = Some((clock, timing));
+        self
+    }
+
+    /// The geometry of the chip.
+    pub fn geometry(&self) -> FlashGeometry {
+        self.chip().geometry
+    }
+
+    /// Read the memory directly, without going through the SPI interface.
+    pub fn read_memory(&self, address: u32, buf: &mut [u8]) {
+        let start = address as usize;
+        buf.copy_from_slice(&self.chip().memory[start..start + buf.len()]);
+    }
+
+    /// Write the memory directly, without going through the SPI interface.
+    /// Unlike a program this can set bits, which is useful to prepare the content of the chip.
+    pub fn write_memory(&self, address: u32, data: &[u8]) {
+        let start = address as usize;
+        self.chip().memory[start..start + data.len()].copy_from_slice(data);
+    }
+
+    /// A copy of the whole memory.
+    pub fn memory(&self) -> Vec<u8> {
+        self.chip().memory.clone()
+    }
+
+    /// A copy of the given security register.
+    pub fn security_register(&self, register: SecurityRegister) -> [u8; 256] {
+        self.chip().security_registers[register as usize - 1]
+    }
+
+    /// The current value of status registers 1, 2 and 3, as the chip would return them.
+    pub fn status_registers(&self) -> [u8; 3] {
+        let chip = self.chip();
+        [
+            chip.status_register_1().bits(),
+            chip.status_register_2().bits(),
+            chip.status_register_3(),
+        ]
+    }
+
+    /// Whether the chip is in the power-down state.
+    pub fn is_powered_down(&self) -> bool {
+        self.chip().powered_down
+    }
+
+    /// Whether the chip is executing an operation, or has suspended one.
+    pub fn is_busy(&self) -> bool {
+        self.chip().is_busy()
+    }
+
+    /// Set the number of status register reads that report BUSY after an operation, when there's no clock.
+    /// Defaults to [DEFAULT_BUSY_POLLS].
+    pub fn set_busy_polls(&self, polls: u32) {
+        self.chip().busy_polls = polls;
+    }
+
+    /// Remove and restore the power of the chip.
+    ///
+    /// This resets the volatile state: the status registers are reloaded from their non-volatile values,
+    /// all individual blocks are locked and any operation in progress is abandoned.
+    pub fn power_cycle(&self) {
+        self.chip().power_up();
+    }
+
+    fn chip(&self) -> MutexGuard<'_, Chip> {
+        self.chip.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Exchange the bytes of the operations while the chip select is low, and execute the instruction
+    /// when it's raised again.
+    fn run(&self, operations: &mut [Operation<'_, u8>]) {
+        let mut chip = self.chip();
+
+        for operation in operations.iter_mut() {
+            match operation {
+                Operation::Read(buf) => buf.iter_mut().for_each(|byte| *byte = chip.exchange(0)),
+                Operation::Write(buf) => buf.iter().for_each(|&byte| {
+                    chip.exchange(byte);
+                }),
+                Operation::Transfer(read, write) => {
+                    for i in 0..read.len().max(write.len()) {
+                        let miso = chip.exchange(write.get(i).copied().unwrap_or(0));
+                        if let Some(byte) = read.get_mut(i) {
+                            *byte = miso;
+                        }
+                    }
+                }
+                Operation::TransferInPlace(buf) => {
+                    buf.iter_mut().for_each(|byte| *byte = chip.exchange(*byte))
+                }
+                Operation::DelayNs(ns) => {
+                    if let Some((clock, _)) = &chip.clock {
+                        clock.advance_ns(*ns as u64);
+                    }
+                }
+            }
+        }
+
+        chip.chip_deselect();
+    }
+}
+
+impl core::fmt::Debug for SimChip {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SimChip")
+            .field("geometry", &self.geometry())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ErrorType for SimChip {
+    type Error = Infallible;
+}
+
+impl embedded_hal::spi::SpiDevice for SimChip {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+        self.run(operations);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_hal_async::spi::SpiDevice for SimChip {
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Infallible> {
+        self.run(operations);
+        Ok(())
+    }
+}
+
+impl Command {
+    const ALL: [Self; 53] = [
+        Self::PageProgram,
+        Self::QuadPageProgram,
+        Self::ReadData,
+        Self::FastRead,
+        Self::FastReadDualOutput,
+        Self::FastReadDualIo,
+        Self::FastReadQuadOutput,
+        Self::FastReadQuadIo,
+        Self::ReadStatusRegister1,
+        Self::ReadStatusRegister2,
+        Self::ReadStatusRegister3,
+        Self::WriteStatusRegister1,
+        Self::WriteStatusRegister2,
+        Self::WriteStatusRegister3,
+        Self::VolatileStatusRegisterWriteEnable,
+        Self::WriteEnable,
+        Self::SectorErase,
+        Self::UniqueId,
+        Self::ReadSfdp,
+        Self::ManufacturerDeviceId,
+        Self::JedecId,
+        Self::Block32Erase,
+        Self::Block64Erase,
+        Self::ReadData4,
+        Self::FastRead4,
+        Self::FastReadDualOutput4,
+        Self::FastReadDualIo4,
+        Self::FastReadQuadOutput4,
+        Self::FastReadQuadIo4,
+        Self::PageProgram4,
+        Self::QuadPageProgram4,
+        Self::SectorErase4,
+        Self::Block64Erase4,
+        Self::Enter4ByteAddressMode,
+        Self::Exit4ByteAddressMode,
+        Self::ChipErase,
+        Self::EnableReset,
+        Self::PowerDown,
+        Self::ReleasePowerDown,
+        Self::Reset,
+        Self::EnterQpiMode,
+        Self::ExitQpiMode,
+        Self::SetReadParameters,
+        Self::IndividualBlockLock,
+        Self::IndividualBlockUnlock,
+        Self::ReadBlockLock,
+        Self::GlobalBlockLock,
+        Self::GlobalBlockUnlock,
+        Self::ReadSecurityRegister,
+        Self::ProgramSecurityRegister,
+        Self::EraseProgramSuspend,
+        Self::EraseProgramResume,
+        Self::EraseSecurityRegister,
+    ];
+
+    fn from_opcode(opcode: u8) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|command| *command as u8 == opcode)
+    }
+
+    /// The number of address bytes and of dummy or mode bytes that follow the opcode on a single lane.
+    fn header(&self, four_byte_address_mode: bool) -> (usize, usize) {
+        use Command::*;
+
+        let width = if four_byte_address_mode {
+            AddressWidth::FourByte.n_bytes()
+        } else {
+            AddressWidth::ThreeByte.n_bytes()
+        };
+
+        match self {
+            ReadData
+            | PageProgram
+            | QuadPageProgram
+            | SectorErase
+            | Block32Erase
+            | Block64Erase
+            | IndividualBlockLock
+            | IndividualBlockUnlock
+            | ReadBlockLock
+            | ProgramSecurityRegister
+            | EraseSecurityRegister => (width, 0),
+            FastRead | FastReadDualOutput | FastReadDualIo | FastReadQuadOutput
+            | ReadSecurityRegister => (width, 1),
+            FastReadQuadIo => (width, 2),
+            ReadData4 | PageProgram4 | QuadPageProgram4 | SectorErase4 | Block64Erase4 => (4, 0),
+            FastRead4 | FastReadDualOutput4 | FastReadDualIo4 | FastReadQuadOutput4 => (4, 1),
+            FastReadQuadIo4 => (4, 2),
+            ReadSfdp => (3, 1),
+            ManufacturerDeviceId => (3, 0),
+            UniqueId => (0, 4),
+            ReleasePowerDown => (0, 3),
+            _ => (0, 0),
+        }
+    }
+}
+
+/// The state of the simulated chip.
+struct Chip {
+    geometry: FlashGeometry,
+    memory: Vec<u8>,
+    security_registers: [[u8; 256]; 3],
+    sfdp: Vec<u8>,
+    /// The status registers, without the BUSY, WEL and SUS bits.
+    status: [u8; 3],
+    non_volatile_status: [u8; 3],
+    write_enabled: bool,
+    volatile_write_enabled: bool,
+    busy_polls: u32,
+    busy_polls_left: u32,
+    clock: Option<(VirtualClock, Timing)>,
+    /// The time on the clock at which the BUSY bit is cleared.
+    busy_until_ns: u64,
+    /// Whether the operation in progress can be suspended.
+    suspendable: bool,
+    /// The start address and the previous content of the memory changed by the last program or erase.
+    last_operation: Option<(u32, Vec<u8>)>,
+    /// The number of programs and erases that were started.
+    memory_operations: u64,
+    /// The busy polls or the time in ns that were left when the operation was suspended.
+    suspended: Option<u64>,
+    four_byte_address_mode: bool,
+    qpi_mode: bool,
+    reset_enabled: bool,
+    powered_down: bool,
+    /// The individual block lock of every sector.
+    sector_locks: Vec<bool>,
+    /// The bytes that were received since the chip select went low.
+    received: Vec<u8>,
+    /// The instruction that is being received, once its address is complete.
+    instruction: Option<(Command, usize, u32)>,
+}
+
+impl Chip {
+    fn new(geometry: FlashGeometry) -> Self {
+        let mut chip = Self {
+            geometry,
+            memory: vec![0xFF; geometry.capacity() as usize],
+            security_registers: [[0xFF; 256]; 3],
+            sfdp: sfdp_table(geometry),
+            status: [0; 3],
+            non_volatile_status: [0, 0, StatusRegister3::default().bits()],
+            write_enabled: false,
+            volatile_write_enabled: false,
+            busy_polls: DEFAULT_BUSY_POLLS,
+            busy_polls_left: 0,
+            clock: None,
+            busy_until_ns: 0,
+            suspendable: false,
+            last_operation: None,
+            memory_operations: 0,
+            suspended: None,
+            four_byte_address_mode: false,
+            qpi_mode: false,
+            reset_enabled: false,
+            powered_down: false,
+            sector_locks: vec![true; geometry.n_sectors() as usize],
+            received: Vec::new(),
+            instruction: None,
+        };
+        chip.power_up();
+        chip
+    }
+
+    fn power_up(&mut self) {
+        self.reset();
+        self.status = self.non_volatile_status;
+        self.powered_down = false;
+        self.received.clear();
+        self.instruction = None;
+    }
+
+    /// Reset the volatile state, as a software reset does.
+    fn reset(&mut self) {
+        self.write_enabled = false;
+        self.volatile_write_enabled = false;
+        self.busy_polls_left = 0;
+        self.busy_until_ns = 0;
+        self.suspended = None;
+        self.last_operation = None;
+        self.four_byte_address_mode = self.non_volatile_status[2] & ADP != 0;
+        self.qpi_mode = false;
+        self.reset_enabled = false;
+        self.sector_locks.fill(true);
+    }
+
+    fn status_register_1(&self) -> StatusRegister1 {
+        StatusRegister1 {
+            busy: self.busy(),
+            wel: self.write_enabled,
+            ..StatusRegister1::from_bits(self.status[0])
+        }
+    }
+
+    fn status_register_2(&self) -> StatusRegister2 {
+        StatusRegister2 {
+            sus: self.suspended.is_some(),
+            ..StatusRegister2::from_bits(self.status[1])
+        }
+    }
+
+    /// Status register 3, with the ADS bit of the 4-byte address chips.
+    fn status_register_3(&self) -> u8 {
+        StatusRegister3::from_bits(self.status[2]).bits() | self.four_byte_address_mode as u8
+    }
+
+    /// The capacity code of the JEDEC ID, or 0 for a geometry that isn't one of the Winbond chips.
+    fn capacity_code(&self) -> u8 {
+        WINBOND_CAPACITY_CODES
+            .iter()
+            .find(|(_, geometry)| *geometry == self.geometry)
+            .map_or(0, |(code, _)| *code)
+    }
+
+    /// The device ID, which is one less than the capacity code, except for the 0x19 of the W25Q512.
+    fn device_id(&self) -> u8 {
+        match self.capacity_code() {
+            0x20 => 0x19,
+            code => code.saturating_sub(1),
+        }
+    }
+
+    /// Whether the chip listens to the instruction in its current state.
+    fn accepts(&self, command: Command) -> bool {
+        if self.powered_down {
+            command == Command::ReleasePowerDown
+        } else if self.qpi_mode {
+            command == Command::ExitQpiMode
+        } else if self.busy() {
+            matches!(
+                command,
+                Command::ReadStatusRegister1
+                    | Command::ReadStatusRegister2
+                    | Command::ReadStatusRegister3
+                    | Command::EraseProgramSuspend
+            )
+        } else {
+            true
+        }
+    }
+
+    /// The decoded instruction, the length of its header and its address once the header is complete.
+    fn decode(&self) -> Option<(Command, usize, u32)> {
+        let command = Command::from_opcode(*self.received.first()?)?;
+        let (address_bytes, dummy_bytes) = command.header(self.four_byte_address_mode);
+        let address = self
+            .received
+            .get(1..1 + address_bytes)?
+            .iter()
+            .fold(0, |address, &byte| address << 8 | byte as u32);
+
+        Some((command, 1 + address_bytes + dummy_bytes, address))
+    }
+
+    /// Shift one byte in and out while the chip select is low.
+    fn exchange(&mut self, mosi: u8) -> u8 {
+        if self.instruction.is_none() {
+            self.instruction = self.decode();
+        }
+
+        let miso = self.output(self.received.len());
+        self.received.push(mosi);
+        miso
+    }
+
+    /// The byte that's shifted out at the given position of the instruction. Undriven bytes read as 0xFF.
+    fn output(&self, position: usize) -> u8 {
+        let Some((command, header_length, address)) = self.instruction else {
+            return 0xFF;
+        };
+        let Some(index) = position.checked_sub(header_length) else {
+            return 0xFF;
+        };
+        if !self.accepts(command) {
+            return 0xFF;
+        }
+
+        use Command::*;
+        match command {
+            ReadData | FastRead | FastReadDualOutput | FastReadDualIo | FastReadQuadOutput
+            | FastReadQuadIo | ReadData4 | FastRead4 | FastReadDualOutput4 | FastReadDualIo4
+            | FastReadQuadOutput4 | FastReadQuadIo4 => {
+                let address = (address as usize + index) % self.memory.len();
+                self.memory[address]
+            }
+            ReadStatusRegister1 => self.status_register_1().bits(),
+            ReadStatusRegister2 => self.status_register_2().bits(),
+            ReadStatusRegister3 => self.status_register_3(),
+            JedecId => [WINBOND_MANUFACTURER_ID, MEMORY_TYPE, self.capacity_code()][index % 3],
+            ManufacturerDeviceId => {
+                [WINBOND_MANUFACTURER_ID, self.device_id()][(address as usize + index) % 2]
+            }
+            ReleasePowerDown => self.device_id(),
+            UniqueId => UNIQUE_ID[index % UNIQUE_ID.len()],
+            ReadSfdp => self
+                .sfdp
+                .get(address as usize + index)
+                .copied()
+                .unwrap_or(0xFF),
+            ReadBlockLock => self.is_locked(address) as u8,
+            ReadSecurityRegister => match security_register_index(address) {
+                Some(register) => {
+                    let offset = (address as usize + index) % SECURITY_REGISTER_SIZE as usize;
+                    self.security_registers[register][offset]
+                }
+                None => 0xFF,
+            },
+            _ => 0xFF,
+        }
+    }
+
+    /// Execute the received instruction when the chip select goes high.
+    fn chip_deselect(&mut self) {
+        let decoded = self.instruction.take().or_else(|| self.decode());
+        let received = core::mem::take(&mut self.received);
+        let reset_enabled = core::mem::take(&mut self.reset_enabled);
+
+        let Some((command, header_length, address)) = decoded else {
+            return;
+        };
+        if !self.accepts(command) {
+            return;
+        }
+        let data = received.get(header_length..).unwrap_or_default();
+
+        use Command::*;
+        match command {
+            ReadStatusRegister1 => {
+                self.busy_polls_left = self.busy_polls_left.saturating_sub(1);
+            }
+            WriteEnable => self.write_enabled = true,
+            VolatileStatusRegisterWriteEnable => self.volatile_write_enabled = true,
+            WriteStatusRegister1 => self.write_status_registers(0, data),
+            WriteStatusRegister2 => self.write_status_registers(1, data),
+            WriteStatusRegister3 => self.write_status_registers(2, data),
+            PageProgram | QuadPageProgram | PageProgram4 | QuadPageProgram4 => {
+                self.program(address, data)
+            }
+            SectorErase | SectorErase4 => self.erase(address, EraseUnit::Sector),
+            Block32Erase => self.erase(address, EraseUnit::Block32K),
+            Block64Erase | Block64Erase4 => self.erase(address, EraseUnit::Block64K),
+            ChipErase => self.erase_chip(),
+            ProgramSecurityRegister => self.program_security_register(address, data),
+            EraseSecurityRegister => self.erase_security_register(address),
+            IndividualBlockLock | IndividualBlockUnlock => {
+                if self.take_write_enable() {
+                    let unit = self.geometry.lock_unit(address % self.geometry.capacity());
+                    self.set_locks(unit, command == IndividualBlockLock);
+                }
+            }
+            GlobalBlockLock | GlobalBlockUnlock => {
+                if self.take_write_enable() {
+                    self.sector_locks.fill(command == GlobalBlockLock);
+                }
+            }
+            Enter4ByteAddressMode => {
+                self.four_byte_address_mode =
+                    self.geometry.address_width() == AddressWidth::FourByte
+            }
+            Exit4ByteAddressMode => self.four_byte_address_mode = false,
+            EnterQpiMode => self.qpi_mode = StatusRegister2::from_bits(self.status[1]).qe,
+            ExitQpiMode => self.qpi_mode = false,
+            EnableReset => self.reset_enabled = true,
+            Reset => {
+                if reset_enabled {
+                    self.reset();
+                    self.status = self.non_volatile_status;
+                }
+            }
+            PowerDown => self.powered_down = true,
+            ReleasePowerDown => self.powered_down = false,
+            EraseProgramSuspend => {
+                if self.busy() && self.suspendable && self.suspended.is_none() {
+                    self.suspended = Some(self.busy_left());
+                    // Suspending takes tSUS with a clock
+                    let latency = match &self.clock {
+                        Some((_, timing)) => {
+                            timing.duration_ns(BusyOperation::Suspend, self.geometry)
+                        }
+                        None => 0,
+                    };
+                    self.set_busy_left(latency);
+                }
+            }
+            EraseProgramResume => {
+                if let Some(busy_left) = self.suspended.take() {
+                    self.set_busy_left(busy_left);
+                }
+            }
+            // Set Read Parameters is only recognized in QPI mode
+            SetReadParameters | ReadStatusRegister2 | ReadStatusRegister3 | ReadData | FastRead
+            | FastReadDualOutput | FastReadDualIo | FastReadQuadOutput | FastReadQuadIo
+            | ReadData4 | FastRead4 | FastReadDualOutput4 | FastReadDualIo4
+            | FastReadQuadOutput4 | FastReadQuadIo4 | UniqueId | ReadSfdp
+            | ManufacturerDeviceId | JedecId | ReadBlockLock | ReadSecurityRegister => {}
+        }
+
+        if command != WriteStatusRegister1
+            && command != WriteStatusRegister2
+            && command != WriteStatusRegister3
+            && command != VolatileStatusRegisterWriteEnable
+        {
+            self.volatile_write_enabled = false;
+        }
+    }
+
+    /// Clear the write enable latch and return whether it was set.
+    fn take_write_enable(&mut self) -> bool {
+        core::mem::take(&mut self.write_enabled)
+    }
+
+    fn start_operation(&mut self, operation: BusyOperation, suspendable: bool) {
+        let busy_left = match &self.clock {
+            Some((_, timing)) => timing.duration_ns(operation, self.geometry),
+            None => self.busy_polls as u64,
+        };
+        self.set_busy_left(busy_left);
+        self.suspendable = suspendable;
+        self.last_operation = None;
+    }
+
+    /// Start a program or erase that changes the given range of the memory,
+    /// and keep the previous content so the operation can be interrupted.
+    fn start_memory_operation(
+        &mut self,
+        range: Range<u32>,
+        operation: BusyOperation,
+        suspendable: bool,
+        change: impl FnOnce(&mut [u8]),
+    ) {
+        let memory = &mut self.memory[range.start as usize..range.end as usize];
+        let previous = memory.to_vec();
+        change(memory);
+
+        self.start_operation(operation, suspendable);
+        self.last_operation = Some((range.start, previous));
+        self.memory_operations += 1;
+    }
+
+    /// Whether an operation is in progress or suspended.
+    fn is_busy(&self) -> bool {
+        self.busy() || self.suspended.is_some()
+    }
+
+    /// Whether the BUSY bit is set.
+    fn busy(&self) -> bool {
+        match &self.clock {
+            Some((clock, _)) => clock.now_ns() < self.busy_until_ns,
+            None => self.busy_polls_left > 0,
+        }
+    }
+
+    /// The time in ns with a clock, or the number of status register reads without one, until BUSY is cleared.
+    fn busy_left(&self) -> u64 {
+        match &self.clock {
+            Some((clock, _)) => self.busy_until_ns.saturating_sub(clock.now_ns()),
+            None => self.busy_polls_left as u64,
+        }
+    }
+
+    fn set_busy_left(&mut self, busy_left: u64) {
+        match &self.clock {
+            Some((clock, _)) => self.busy_until_ns = clock.now_ns() + busy_left,
+            None => self.busy_polls_left = busy_left as u32,
+        }
+    }
+
+    fn write_status_registers(&mut self, first: usize, data: &[u8]) {
+        const ONE_TIME_PROGRAMMABLE: [u8; 3] = [0x00, 0x38, 0x00];
+
+        let mut writable = [0xFC, 0x7B, 0x64];
+        if self.geometry.address_width() == AddressWidth::FourByte {
+            writable[2] |= ADP;
+        }
+
+        let volatile = core::mem::take(&mut self.volatile_write_enabled);
+        let write_enabled = self.take_write_enable();
+
+        // The status registers are locked by SRL until the next power cycle, or forever when SRP is also set
+        if !(volatile || write_enabled) || StatusRegister2::from_bits(self.status[1]).srl {
+            return;
+        }
+
+        // Write Status Register-1 also writes status register 2 when a second byte is sent
+        for (register, &bits) in (first..3).zip(data.iter().take(if first == 0 { 2 } else { 1 })) {
+            let bits = (self.status[register] & !writable[register])
+                | (bits & writable[register])
+                | (self.status[register] & ONE_TIME_PROGRAMMABLE[register]);
+
+            self.status[register] = bits;
+            if !volatile {
+                self.non_volatile_status[register] = bits;
+            }
+        }
+
+        if !volatile {
+            self.start_operation(BusyOperation::StatusRegisterWrite, false);
+        }
+    }
+
+    /// Whether programs and erases of the given range are ignored.
+    fn is_protected(&self, range: Range<u32>) -> bool {
+        if StatusRegister3::from_bits(self.status[2]).wps {
+            return range
+                .step_by(SECTOR_SIZE as usize)
+                .any(|address| self.is_locked(address));
+        }
+
+        let status_register_1 = StatusRegister1::from_bits(self.status[0]);
+        let protection = BlockProtection {
+            bp: status_register_1.bp,
+            tb: status_register_1.tb,
+            sec: status_register_1.sec,
+            cmp: StatusRegister2::from_bits(self.status[1]).cmp,
+        };
+
+        protection
+            .protected_range(self.geometry)
+            .is_some_and(|protected| protected.start < range.end && range.start < protected.end)
+    }
+
+    fn is_locked(&self, address: u32) -> bool {
+        self.sector_locks[((address % self.geometry.capacity()) / SECTOR_SIZE) as usize]
+    }
+
+    fn set_locks(&mut self, range: Range<u32>, locked: bool) {
+        let sectors = (range.start / SECTOR_SIZE) as usize..(range.end / SECTOR_SIZE) as usize;
+        self.sector_locks[sectors].fill(locked);
+    }
+
+    fn program(&mut self, address: u32, data: &[u8]) {
+        let address = address % self.geometry.capacity();
+        let page = address - address % PAGE_SIZE;
+
+        if !self.take_write_enable() || data.is_empty() || self.is_protected(page..page + PAGE_SIZE)
+        {
+            return;
+        }
+
+        self.start_memory_operation(
+            page..page + PAGE_SIZE,
+            BusyOperation::PageProgram,
+            true,
+            |page| program_page(page, (address % PAGE_SIZE) as usize, data),
+        );
+    }
+
+    fn erase(&mut self, address: u32, unit: EraseUnit) {
+        let address = address % self.geometry.capacity();
+        let start = address - address % unit.size();
+
+        self.erase_range(start..start + unit.size(), unit.busy_operation());
+    }
+
+    fn erase_chip(&mut self) {
+        self.erase_range(0..self.geometry.capacity(), BusyOperation::ChipErase);
+    }
+
+    fn erase_range(&mut self, range: Range<u32>, operation: BusyOperation) {
+        if !self.take_write_enable() || self.suspended.is_some() || self.is_protected(range.clone())
+        {
+            return;
+        }
+
+        // A chip erase can't be suspended
+        let suspendable = operation != BusyOperation::ChipErase;
+        self.start_memory_operation(range, operation, suspendable, |memory| memory.fill(0xFF));
+    }
+
+    fn is_security_register_locked(&self, register: usize) -> bool {
+        self.status[1] & (0x08 << register) != 0
+    }
+
+    fn program_security_register(&mut self, address: u32, data: &[u8]) {
+        let write_enabled = self.take_write_enable();
+
+        if let Some(register) = security_register_index(address) {
+            if write_enabled && !data.is_empty() && !self.is_security_register_locked(register) {
+                program_page(
+                    &mut self.security_registers[register],
+                    (address % SECURITY_REGISTER_SIZE) as usize,
+                    data,
+                );
+                self.start_operation(BusyOperation::PageProgram, false);
+            }
+        }
+    }
+
+    fn erase_security_register(&mut self, address: u32) {
+        let write_enabled = self.take_write_enable();
+
+        if let Some(register) = security_register_index(address) {
+            if write_enabled && !self.is_security_register_locked(register) {
+                self.security_registers[register].fill(0xFF);
+                self.start_operation(BusyOperation::SectorErase, false);
+            }
+        }
+    }
+}
+
+/// Program data into a page, starting at the given offset.
+///
+/// The chip latches the data in a page buffer that wraps around, so only the last page of data is kept.
+/// Programming can only clear bits.
+fn program_page(page: &mut [u8], offset: usize, data: &[u8]) {
+    let mut buffer = vec![0xFF; page.len()];
+
+    for (i, &byte) in data.iter().enumerate() {
+        buffer[(offset + i) % page.len()] = byte;
+    }
+
+    for (byte, programmed) in page.iter_mut().zip(buffer) {
+        *byte &= programmed;
+    }
+}
+
+/// The index of the security register that contains the address, if any.
+fn security_register_index(address: u32) -> Option<usize> {
+    match (address >> 12) & 0x0F {
+        register @ 1..=3 if address >> 16 == 0 => Some(register as usize - 1),
+        _ => None,
+    }
+}
+
+/// The SFDP data of a chip with the given geometry: the SFDP header, one parameter header
+/// and a JESD216 Basic Flash Parameter Table.
+fn sfdp_table(geometry: FlashGeometry) -> Vec<u8> {
+    const TABLE_POINTER: u8 = 0x10;
+
+    let address_bytes = match geometry.address_width() {
+        AddressWidth::ThreeByte => 0b00,
+        AddressWidth::FourByte => 0b01,
+    };
+
+    let dwords: [u32; 9] = [
+        // 4 KiB erase, 1-1-2, 1-2-2, 1-4-4 and 1-1-4 fast reads
+        0b01 | (Command::SectorErase as u32) << 8
+            | 1 << 16
+            | address_bytes << 17
+            | 1 << 20
+            | 1 << 21
+            | 1 << 22,
+        // The density in bits minus one
+        geometry.capacity() * 8 - 1,
+        // 1-4-4 with 2 mode clocks and 4 dummy clocks, 1-1-4 with 8 dummy clocks
+        2 << 5
+            | 4
+            | (Command::FastReadQuadIo as u32) << 8
+            | (8 | (Command::FastReadQuadOutput as u32) << 8) << 16,
+        // 1-1-2 with 8 dummy clocks, 1-2-2 with 4 mode clocks
+        8 | (Command::FastReadDualOutput as u32) << 8
+            | (4 << 5 | (Command::FastReadDualIo as u32) << 8) << 16,
+        // No 2-2-2 and 4-4-4 fast reads
+        0,
+        0,
+        0,
+        // 4 KiB and 32 KiB erase types
+        12 | (Command::SectorErase as u32) << 8 | (15 | (Command::Block32Erase as u32) << 8) << 16,
+        // 64 KiB erase type
+        16 | (Command::Block64Erase as u32) << 8,
+    ];
+
+    let mut table = vec![
+        // "SFDP", revision 1.0, one parameter header, single SPI access protocol
+        b'S',
+        b'F',
+        b'D',
+        b'P',
+        0x00,
+        0x01,
+        0x00,
+        0xFF,
+        // Basic Flash Parameter Table 1.0 of 9 DWORDs
+        0x00,
+        0x00,
+        0x01,
+        dwords.len() as u8,
+        TABLE_POINTER,
+        0x00,
+        0x00,
+        0xFF,
+    ];
+    table.extend(dwords.iter().flat_map(|dword| dword.to_le_bytes()));
+    table
+}
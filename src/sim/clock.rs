@@ -0,0 +1,113 @@
+//! A virtual clock for the simulated chip, so operations take a realistic time without waiting for it.
+//!
+//! The clock only advances when a [SimDelay] waits, or when a `DelayNs` operation is part of a SPI transaction.
+//! A [SimChip](super::SimChip) with a clock reports BUSY until the duration of the operation has passed,
+//! instead of for a number of status register reads. Give the driver a [SimDelay] with `with_delay`
+//! to poll the status at the same intervals and with the same timeouts as on hardware.
+
+use crate::{BusyOperation, FlashGeometry};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// The durations of the operations of a chip with a clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Timing {
+    /// The typical durations from the datasheet.
+    #[default]
+    Typical,
+    /// The maximum durations from the datasheet, which the driver waits for before it times out.
+    Maximum,
+    /// The maximum durations plus the given percentage, to make the driver time out.
+    Slow(u32),
+}
+
+impl Timing {
+    /// The duration of the operation in ns.
+    pub(super) fn duration_ns(&self, operation: BusyOperation, geometry: FlashGeometry) -> u64 {
+        let duration_us = match self {
+            Timing::Typical => typical_duration_us(operation, geometry),
+            Timing::Maximum => operation.max_duration_us(geometry),
+            Timing::Slow(percent) => {
+                operation.max_duration_us(geometry) * (100 + *percent as u64) / 100
+            }
+        };
+
+        duration_us * 1_000
+    }
+}
+
+/// The typical duration from the datasheet (tPP, tSE, tBE1, tBE2, tCE, tW and tSUS) in µs.
+fn typical_duration_us(operation: BusyOperation, geometry: FlashGeometry) -> u64 {
+    match operation {
+        BusyOperation::PageProgram => 400,
+        BusyOperation::SectorErase => 45_000,
+        BusyOperation::Block32KErase => 120_000,
+        BusyOperation::Block64KErase => 150_000,
+        // 10 s for the 4 MiB W25Q32, scaling with the capacity
+        BusyOperation::ChipErase => 2_500_000 * geometry.capacity().div_ceil(1 << 20) as u64,
+        BusyOperation::StatusRegisterWrite => 10_000,
+        // The datasheet only gives a maximum
+        BusyOperation::Suspend => 20,
+    }
+}
+
+/// A clock that is shared between the simulated chip and the delays of the code under test.
+#[derive(Debug, Clone, Default)]
+pub struct VirtualClock {
+    now_ns: Arc<AtomicU64>,
+}
+
+impl VirtualClock {
+    /// Create a clock at time 0.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The time since the clock was created in ns.
+    pub fn now_ns(&self) -> u64 {
+        self.now_ns.load(Ordering::Relaxed)
+    }
+
+    /// The time since the clock was created in µs.
+    pub fn now_us(&self) -> u64 {
+        self.now_ns() / 1_000
+    }
+
+    /// Move the clock forward.
+    pub fn advance_ns(&self, ns: u64) {
+        self.now_ns.fetch_add(ns, Ordering::Relaxed);
+    }
+
+    /// A delay that advances this clock.
+    pub fn delay(&self) -> SimDelay {
+        SimDelay {
+            clock: self.clone(),
+        }
+    }
+}
+
+/// A delay that advances a [VirtualClock] instead of waiting.
+#[derive(Debug, Clone)]
+pub struct SimDelay {
+    clock: VirtualClock,
+}
+
+impl SimDelay {
+    /// The clock this delay advances.
+    pub fn clock(&self) -> &VirtualClock {
+        &self.clock
+    }
+}
+
+impl embedded_hal::delay::DelayNs for SimDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        self.clock.advance_ns(ns as u64);
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_hal_async::delay::DelayNs for SimDelay {
+    async fn delay_ns(&mut self, ns: u32) {
+        self.clock.advance_ns(ns as u64);
+    }
+}
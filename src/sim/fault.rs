@@ -0,0 +1,223 @@
+//! Power loss injection for the simulated chip, to test how code built on the driver recovers from brown-outs.
+//!
+//! A [FaultyChip] forwards the SPI transactions to a [SimChip] until the power is cut. The power can be cut
+//! after a number of transactions, or right after a program or erase instruction so it is interrupted.
+//! A program or erase that's still in progress when the power is cut is interrupted at a random byte:
+//! the bytes before it are done, the bits that the operation changes in the rest of its page or erase unit
+//! are each done or not at random. This is the partially programmed or partially erased content that
+//! recovery logic has to deal with.
+//!
+//! Without power every transaction fails with [PowerLoss]. [FaultyChip::restore_power] powers the chip up again,
+//! after which a new driver can be created to run the recovery.
+
+use super::{Chip, SimChip};
+use embedded_hal::spi::{ErrorKind, ErrorType, Operation};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// The SPI error of a [FaultyChip] that has lost its power.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerLoss;
+
+impl embedded_hal::spi::Error for PowerLoss {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+/// A small xorshift pseudo random number generator, so the faults are reproducible from a seed.
+#[derive(Debug, Clone)]
+pub struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    /// Create a generator from a seed. Any seed can be used, including 0.
+    pub fn new(seed: u64) -> Self {
+        const MIX: u64 = 0x9E37_79B9_7F4A_7C15;
+
+        // The state must never be 0
+        let state = seed ^ MIX;
+        Self {
+            state: if state == 0 { MIX } else { state },
+        }
+    }
+
+    /// The next pseudo random number.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// A pseudo random number in `0..bound`. Returns 0 if the bound is 0.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64().checked_rem(bound).unwrap_or(0)
+    }
+}
+
+/// The planned faults and the power state.
+#[derive(Debug)]
+struct Faults {
+    rng: XorShift,
+    has_power: bool,
+    /// The number of transactions to forward before the power is cut.
+    transactions_left: Option<u64>,
+    /// The number of programs and erases to start before the power is cut during the next one.
+    memory_operations_left: Option<u64>,
+    transactions: u64,
+}
+
+/// A [SimChip] whose power can be cut.
+///
+/// Like [SimChip] this is a handle to shared state, so a clone can be kept to plan the faults
+/// and restore the power while the driver owns the other one.
+#[derive(Debug, Clone)]
+pub struct FaultyChip {
+    chip: SimChip,
+    faults: Arc<Mutex<Faults>>,
+}
+
+impl FaultyChip {
+    /// Wrap a chip. The seed determines where the interrupted operations are cut and which bits they leave.
+    pub fn new(chip: SimChip, seed: u64) -> Self {
+        Self {
+            chip,
+            faults: Arc::new(Mutex::new(Faults {
+                rng: XorShift::new(seed),
+                has_power: true,
+                transactions_left: None,
+                memory_operations_left: None,
+                transactions: 0,
+            })),
+        }
+    }
+
+    /// The wrapped chip, to inspect its memory.
+    pub fn chip(&self) -> &SimChip {
+        &self.chip
+    }
+
+    /// Whether the chip has power.
+    pub fn has_power(&self) -> bool {
+        self.faults().has_power
+    }
+
+    /// The number of SPI transactions that were forwarded to the chip. Every instruction is one transaction.
+    ///
+    /// A fault-free run of the code under test gives the range to pick [Self::cut_power_after] from.
+    pub fn transactions(&self) -> u64 {
+        self.faults().transactions
+    }
+
+    /// Cut the power after the given number of transactions. The next transaction fails with [PowerLoss].
+    pub fn cut_power_after(&self, transactions: u64) {
+        let mut faults = self.faults();
+        faults.transactions_left = Some(transactions);
+    }
+
+    /// Cut the power after a random number of transactions below the given bound.
+    pub fn cut_power_randomly(&self, max_transactions: u64) {
+        let mut faults = self.faults();
+        faults.transactions_left = Some(faults.rng.below(max_transactions));
+    }
+
+    /// Cut the power right after the program or erase instruction with the given index is sent, counting from 0,
+    /// so that operation is interrupted.
+    pub fn cut_power_during(&self, memory_operation: u64) {
+        self.faults().memory_operations_left = Some(memory_operation);
+    }
+
+    /// Cut the power now, interrupting the operation that's in progress.
+    pub fn cut_power(&self) {
+        let mut faults = self.faults();
+        self.cut_power_locked(&mut faults);
+    }
+
+    /// Restore the power. The chip powers up with its non-volatile state.
+    ///
+    /// Faults that were planned but didn't happen yet are cancelled.
+    pub fn restore_power(&self) {
+        let mut faults = self.faults();
+        faults.has_power = true;
+        faults.transactions_left = None;
+        faults.memory_operations_left = None;
+        self.chip.power_cycle();
+    }
+
+    fn faults(&self) -> MutexGuard<'_, Faults> {
+        self.faults.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn cut_power_locked(&self, faults: &mut Faults) {
+        let mut chip = self.chip.chip();
+
+        if chip.is_busy() {
+            interrupt_operation(&mut chip, &mut faults.rng);
+        }
+
+        chip.power_up();
+        faults.has_power = false;
+        faults.transactions_left = None;
+        faults.memory_operations_left = None;
+    }
+
+    fn run(&self, operations: &mut [Operation<'_, u8>]) -> Result<(), PowerLoss> {
+        let mut faults = self.faults();
+
+        if faults.has_power && faults.transactions_left == Some(0) {
+            self.cut_power_locked(&mut faults);
+        }
+        if !faults.has_power {
+            return Err(PowerLoss);
+        }
+
+        let memory_operations = self.chip.chip().memory_operations;
+        self.chip.run(operations);
+        faults.transactions += 1;
+        faults.transactions_left = faults.transactions_left.map(|left| left - 1);
+
+        if self.chip.chip().memory_operations != memory_operations {
+            match faults.memory_operations_left {
+                Some(0) => self.cut_power_locked(&mut faults),
+                Some(left) => faults.memory_operations_left = Some(left - 1),
+                None => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Leave the last program or erase partially done, as if the power was cut at a random byte.
+fn interrupt_operation(chip: &mut Chip, rng: &mut XorShift) {
+    let Some((start, previous)) = chip.last_operation.take() else {
+        return;
+    };
+
+    let start = start as usize;
+    let memory = &mut chip.memory[start..start + previous.len()];
+    let cut = rng.below(previous.len() as u64) as usize;
+
+    for (byte, old) in memory.iter_mut().zip(previous).skip(cut) {
+        let changed = *byte ^ old;
+        *byte = old ^ (changed & rng.next_u64() as u8);
+    }
+}
+
+impl ErrorType for FaultyChip {
+    type Error = PowerLoss;
+}
+
+impl embedded_hal::spi::SpiDevice for FaultyChip {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), PowerLoss> {
+        self.run(operations)
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_hal_async::spi::SpiDevice for FaultyChip {
+    async fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), PowerLoss> {
+        self.run(operations)
+    }
+}
@@ -0,0 +1,339 @@
+//! Recording and replaying of the SPI transactions of the driver, to pin its wire behaviour in regression tests.
+//!
+//! A [Recorder] wraps any `SpiDevice` and logs a [Transaction] for every transaction: the opcode, the address
+//! and the number of bytes written and read after the header. A [Trace] is written as one transaction per line:
+//!
+//! ```text
+//! 06
+//! 02 @000100 w256
+//! 05 r1
+//! ```
+//!
+//! The opcode and the address are hexadecimal, a 4-byte address is written with 8 digits.
+//! Dummy and mode bytes are implied by the opcode and not part of the trace.
+//!
+//! A [Replayer] wraps an `SpiDevice` that answers the transactions, like a [SimChip](super::SimChip),
+//! and panics as soon as a transaction differs from the expected trace.
+
+use crate::{AddressWidth, Command};
+use core::fmt::{self, Display};
+use core::str::FromStr;
+use embedded_hal::spi::{ErrorType, Operation};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::vec::Vec;
+
+/// The shape of one SPI transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transaction {
+    /// The first byte of the transaction. Transactions that don't start with a write are recorded with opcode 0.
+    pub opcode: u8,
+    /// The address that follows the opcode, if any.
+    pub address: Option<(u32, AddressWidth)>,
+    /// The number of bytes written after the header.
+    pub write: usize,
+    /// The number of bytes read.
+    pub read: usize,
+}
+
+impl Transaction {
+    /// Describe the transaction. The first write is the header with the opcode, the address and the dummy bytes.
+    pub fn from_operations(operations: &[Operation<'_, u8>]) -> Self {
+        let (header, payload): (&[u8], _) = match operations.split_first() {
+            Some((Operation::Write(header), payload)) => (header, payload),
+            _ => (&[], operations),
+        };
+
+        let opcode = header.first().copied().unwrap_or(0);
+        let dummy_bytes = Command::from_opcode(opcode)
+            .map(|command| command.header(false).1)
+            .unwrap_or(0);
+        let address_bytes = header
+            .get(1..header.len().saturating_sub(dummy_bytes))
+            .unwrap_or_default();
+        let width = match address_bytes.len() {
+            3 => Some(AddressWidth::ThreeByte),
+            4 => Some(AddressWidth::FourByte),
+            _ => None,
+        };
+        let address = width.map(|width| {
+            let address = address_bytes
+                .iter()
+                .fold(0, |address, &byte| address << 8 | byte as u32);
+            (address, width)
+        });
+
+        let mut transaction = Self {
+            opcode,
+            address,
+            write: 0,
+            read: 0,
+        };
+
+        for operation in payload {
+            match operation {
+                Operation::Read(buf) => transaction.read += buf.len(),
+                Operation::Write(buf) => transaction.write += buf.len(),
+                Operation::Transfer(read, write) => {
+                    transaction.read += read.len();
+                    transaction.write += write.len();
+                }
+                Operation::TransferInPlace(buf) => {
+                    transaction.read += buf.len();
+                    transaction.write += buf.len();
+                }
+                Operation::DelayNs(_) => {}
+            }
+        }
+
+        transaction
+    }
+}
+
+impl Display for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02X}", self.opcode)?;
+
+        match self.address {
+            Some((address, AddressWidth::ThreeByte)) => write!(f, " @{address:06X}")?,
+            Some((address, AddressWidth::FourByte)) => write!(f, " @{address:08X}")?,
+            None => {}
+        }
+        if self.write > 0 {
+            write!(f, " w{}", self.write)?;
+        }
+        if self.read > 0 {
+            write!(f, " r{}", self.read)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The error when a transaction or a trace can't be parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseTraceError {
+    /// The line of the trace that can't be parsed, counting from 1.
+    pub line: usize,
+}
+
+impl Display for ParseTraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid transaction on line {}", self.line)
+    }
+}
+
+impl FromStr for Transaction {
+    type Err = ParseTraceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = ParseTraceError { line: 1 };
+        let mut words = s.split_whitespace();
+
+        let opcode = words.next().ok_or(error)?;
+        let mut transaction = Self {
+            opcode: u8::from_str_radix(opcode, 16).map_err(|_| error)?,
+            address: None,
+            write: 0,
+            read: 0,
+        };
+
+        for word in words {
+            let (kind, value) = word.split_at_checked(1).ok_or(error)?;
+
+            match kind {
+                "@" => {
+                    let width = match value.len() {
+                        6 => AddressWidth::ThreeByte,
+                        8 => AddressWidth::FourByte,
+                        _ => return Err(error),
+                    };
+                    let address = u32::from_str_radix(value, 16).map_err(|_| error)?;
+                    transaction.address = Some((address, width));
+                }
+                "w" => transaction.write = value.parse().map_err(|_| error)?,
+                "r" => transaction.read = value.parse().map_err(|_| error)?,
+                _ => return Err(error),
+            }
+        }
+
+        Ok(transaction)
+    }
+}
+
+/// A sequence of transactions.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Trace {
+    pub transactions: Vec<Transaction>,
+}
+
+impl Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.transactions
+            .iter()
+            .try_for_each(|transaction| writeln!(f, "{transaction}"))
+    }
+}
+
+impl FromStr for Trace {
+    type Err = ParseTraceError;
+
+    /// Parse one transaction per line. Leading whitespace and empty lines are ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let transactions = s
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                line.parse()
+                    .map_err(|_| ParseTraceError { line: index + 1 })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { transactions })
+    }
+}
+
+/// The trace of a [Recorder], shared with it so it can be read while the driver owns the recorder.
+#[derive(Debug, Clone, Default)]
+pub struct TraceLog {
+    trace: Arc<Mutex<Trace>>,
+}
+
+impl TraceLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A copy of the transactions that were recorded so far.
+    pub fn trace(&self) -> Trace {
+        self.lock().clone()
+    }
+
+    /// Take the transactions that were recorded so far, leaving the log empty.
+    pub fn take(&self) -> Trace {
+        core::mem::take(&mut self.lock())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Trace> {
+        self.trace.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A `SpiDevice` that records the transactions to a [TraceLog] before passing them on.
+#[derive(Debug)]
+pub struct Recorder<SPI> {
+    spi: SPI,
+    log: TraceLog,
+}
+
+impl<SPI> Recorder<SPI> {
+    pub fn new(spi: SPI, log: TraceLog) -> Self {
+        Self { spi, log }
+    }
+
+    fn record(&self, operations: &[Operation<'_, u8>]) {
+        self.log
+            .lock()
+            .transactions
+            .push(Transaction::from_operations(operations));
+    }
+}
+
+impl<SPI: ErrorType> ErrorType for Recorder<SPI> {
+    type Error = SPI::Error;
+}
+
+impl<SPI: embedded_hal::spi::SpiDevice> embedded_hal::spi::SpiDevice for Recorder<SPI> {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        self.record(operations);
+        self.spi.transaction(operations)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<SPI: embedded_hal_async::spi::SpiDevice> embedded_hal_async::spi::SpiDevice for Recorder<SPI> {
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        self.record(operations);
+        self.spi.transaction(operations).await
+    }
+}
+
+/// A `SpiDevice` that checks the transactions against an expected trace before passing them on.
+///
+/// Panics on the first transaction that differs from the trace or that isn't in it,
+/// and when it's dropped before the whole trace was replayed.
+#[derive(Debug)]
+pub struct Replayer<SPI> {
+    spi: SPI,
+    expected: Trace,
+    replayed: usize,
+}
+
+impl<SPI> Replayer<SPI> {
+    pub fn new(spi: SPI, expected: Trace) -> Self {
+        Self {
+            spi,
+            expected,
+            replayed: 0,
+        }
+    }
+
+    /// The number of transactions that were replayed so far.
+    pub fn replayed(&self) -> usize {
+        self.replayed
+    }
+
+    fn check(&mut self, operations: &[Operation<'_, u8>]) {
+        let transaction = Transaction::from_operations(operations);
+
+        match self.expected.transactions.get(self.replayed) {
+            Some(expected) if *expected == transaction => self.replayed += 1,
+            Some(expected) => panic!(
+                "transaction {} is `{transaction}`, expected `{expected}`",
+                self.replayed + 1
+            ),
+            None => panic!(
+                "transaction {} is `{transaction}`, expected the end of the trace",
+                self.replayed + 1
+            ),
+        }
+    }
+}
+
+impl<SPI> Drop for Replayer<SPI> {
+    fn drop(&mut self) {
+        if !std::thread::panicking() && self.replayed < self.expected.transactions.len() {
+            panic!(
+                "only {} of the {} transactions were replayed, the next is `{}`",
+                self.replayed,
+                self.expected.transactions.len(),
+                self.expected.transactions[self.replayed]
+            );
+        }
+    }
+}
+
+impl<SPI: ErrorType> ErrorType for Replayer<SPI> {
+    type Error = SPI::Error;
+}
+
+impl<SPI: embedded_hal::spi::SpiDevice> embedded_hal::spi::SpiDevice for Replayer<SPI> {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        self.check(operations);
+        self.spi.transaction(operations)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<SPI: embedded_hal_async::spi::SpiDevice> embedded_hal_async::spi::SpiDevice for Replayer<SPI> {
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        self.check(operations);
+        self.spi.transaction(operations).await
+    }
+}
@@ -0,0 +1,146 @@
+//! Typed access to the three status registers of the chip.
+//!
+//! The bit layout is the one of the W25QxxJV family. Reserved bits of status registers 1 and 2 read as 0 and are written as 0.
+//! The bits of status register 3 without a field differ between chips and are kept as they are.
+
+/// Whether a status register write survives a power cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Volatility {
+    /// The bits are written to the non-volatile register, which takes up to 15 ms and wears the chip.
+    #[default]
+    NonVolatile,
+    /// The bits are only changed until the next power cycle. Uses Write Enable for Volatile Status Register (0x50).
+    Volatile,
+}
+
+/// Status register 1, read with 0x05 and written with 0x01.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StatusRegister1 {
+    /// BUSY: an erase, program or status register write is in progress. Read only.
+    pub busy: bool,
+    /// WEL: the write enable latch is set. Read only.
+    pub wel: bool,
+    /// BP2-BP0: the block protect bits, with BP0 as the least significant bit.
+    pub bp: u8,
+    /// TB: the protected area starts at the bottom instead of the top of the memory.
+    pub tb: bool,
+    /// SEC: the block protect bits protect 4 KiB sectors instead of 64 KiB blocks.
+    pub sec: bool,
+    /// SRP: status register protect, together with SRL and the WP pin.
+    pub srp: bool,
+}
+
+impl StatusRegister1 {
+    pub const fn from_bits(bits: u8) -> Self {
+        Self {
+            busy: bits & 0x01 != 0,
+            wel: bits & 0x02 != 0,
+            bp: (bits >> 2) & 0x07,
+            tb: bits & 0x20 != 0,
+            sec: bits & 0x40 != 0,
+            srp: bits & 0x80 != 0,
+        }
+    }
+
+    pub const fn bits(&self) -> u8 {
+        (self.busy as u8)
+            | (self.wel as u8) << 1
+            | (self.bp & 0x07) << 2
+            | (self.tb as u8) << 5
+            | (self.sec as u8) << 6
+            | (self.srp as u8) << 7
+    }
+}
+
+/// Status register 2, read with 0x35 and written with 0x31.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StatusRegister2 {
+    /// SRL: status register lock, together with SRP.
+    pub srl: bool,
+    /// QE: quad enable, needed for the quad instructions.
+    pub qe: bool,
+    /// LB1: security register 1 is locked. One time programmable.
+    pub lb1: bool,
+    /// LB2: security register 2 is locked. One time programmable.
+    pub lb2: bool,
+    /// LB3: security register 3 is locked. One time programmable.
+    pub lb3: bool,
+    /// CMP: complement protect, inverts the area selected by the block protect bits.
+    pub cmp: bool,
+    /// SUS: an erase or program is suspended. Read only.
+    pub sus: bool,
+}
+
+impl StatusRegister2 {
+    pub const fn from_bits(bits: u8) -> Self {
+        Self {
+            srl: bits & 0x01 != 0,
+            qe: bits & 0x02 != 0,
+            lb1: bits & 0x08 != 0,
+            lb2: bits & 0x10 != 0,
+            lb3: bits & 0x20 != 0,
+            cmp: bits & 0x40 != 0,
+            sus: bits & 0x80 != 0,
+        }
+    }
+
+    pub const fn bits(&self) -> u8 {
+        (self.srl as u8)
+            | (self.qe as u8) << 1
+            | (self.lb1 as u8) << 3
+            | (self.lb2 as u8) << 4
+            | (self.lb3 as u8) << 5
+            | (self.cmp as u8) << 6
+            | (self.sus as u8) << 7
+    }
+}
+
+/// The output driver strength of the chip, set with DRV1-DRV0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DriverStrength {
+    Percent100 = 0b00,
+    Percent75 = 0b01,
+    Percent50 = 0b10,
+    /// The default of most chips.
+    #[default]
+    Percent25 = 0b11,
+}
+
+/// Status register 3, read with 0x15 and written with 0x11.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StatusRegister3 {
+    /// WPS: write protect selection. Uses the individual block locks instead of the block protect bits when set.
+    pub wps: bool,
+    /// DRV1-DRV0: the output driver strength.
+    pub drv: DriverStrength,
+    /// The bits without a field, e.g. ADS and ADP on the 256 and 512 M-bit chips.
+    /// Keep them as they were read, so a read-modify-write doesn't change them.
+    pub other_bits: u8,
+}
+
+impl StatusRegister3 {
+    /// The WPS and DRV1-DRV0 bits.
+    const FIELD_BITS: u8 = 0x64;
+
+    pub const fn from_bits(bits: u8) -> Self {
+        Self {
+            wps: bits & 0x04 != 0,
+            drv: match (bits >> 5) & 0x03 {
+                0b00 => DriverStrength::Percent100,
+                0b01 => DriverStrength::Percent75,
+                0b10 => DriverStrength::Percent50,
+                _ => DriverStrength::Percent25,
+            },
+            other_bits: bits & !Self::FIELD_BITS,
+        }
+    }
+
+    pub const fn bits(&self) -> u8 {
+        (self.wps as u8) << 2 | (self.drv as u8) << 5 | (self.other_bits & !Self::FIELD_BITS)
+    }
+}
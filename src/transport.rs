@@ -0,0 +1,264 @@
+//! The bus the chip is connected to.
+//!
+//! The driver describes every instruction as a sequence of phases (instruction, address, mode bits, dummy cycles and data),
+//! each with their own number of data lanes. A [Transport] executes these instructions,
+//! which makes it possible to use dual and quad SPI peripherals.
+//!
+//! Every [SpiDevice](embedded_hal::spi::SpiDevice) is a single-lane transport, so a normal SPI device can be passed to the driver as is.
+
+use crate::{AddressWidth, Error};
+use embedded_hal::spi::{ErrorType, Operation};
+
+/// The number of data lanes used in a phase of an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Lanes {
+    Single = 1,
+    Dual = 2,
+    Quad = 4,
+}
+
+/// The address phase of an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Address {
+    pub value: u32,
+    pub width: AddressWidth,
+}
+
+impl Address {
+    /// The address bytes as they're sent to the chip, MSB first.
+    pub fn bytes(&self) -> impl Iterator<Item = u8> {
+        let n_bytes = self.width.n_bytes();
+        self.value.to_be_bytes().into_iter().skip(4 - n_bytes)
+    }
+}
+
+/// The data phase of an instruction.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Data<'a> {
+    None,
+    /// Read bytes from the chip into the buffer.
+    Read(&'a mut [u8]),
+    /// Write the bytes to the chip.
+    Write(&'a [u8]),
+}
+
+/// A single instruction that is executed while the chip select is asserted.
+///
+/// The phases are executed in the order of the fields.
+/// The address and the mode bits are sent on the address lanes.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Instruction<'a> {
+    pub opcode: u8,
+    pub opcode_lanes: Lanes,
+    pub address: Option<Address>,
+    pub address_lanes: Lanes,
+    /// Eight mode bits that are sent after the address.
+    pub mode_bits: Option<u8>,
+    /// The number of clock cycles between the address (or mode bits) and the data.
+    pub dummy_cycles: u8,
+    pub data: Data<'a>,
+    pub data_lanes: Lanes,
+}
+
+impl<'a> Instruction<'a> {
+    /// Create an instruction that only consists of the opcode, sent on a single lane.
+    pub const fn new(opcode: u8) -> Self {
+        Self {
+            opcode,
+            opcode_lanes: Lanes::Single,
+            address: None,
+            address_lanes: Lanes::Single,
+            mode_bits: None,
+            dummy_cycles: 0,
+            data: Data::None,
+            data_lanes: Lanes::Single,
+        }
+    }
+
+    /// Set the number of lanes of the opcode, address and data phases.
+    pub fn with_lanes(self, opcode: Lanes, address: Lanes, data: Lanes) -> Self {
+        Self {
+            opcode_lanes: opcode,
+            address_lanes: address,
+            data_lanes: data,
+            ..self
+        }
+    }
+
+    /// Add an address phase.
+    pub fn with_address(self, value: u32, width: AddressWidth) -> Self {
+        Self {
+            address: Some(Address { value, width }),
+            ..self
+        }
+    }
+
+    /// Add mode bits after the address.
+    pub fn with_mode_bits(self, mode_bits: u8) -> Self {
+        Self {
+            mode_bits: Some(mode_bits),
+            ..self
+        }
+    }
+
+    /// Add dummy clock cycles before the data.
+    pub fn with_dummy_cycles(self, dummy_cycles: u8) -> Self {
+        Self {
+            dummy_cycles,
+            ..self
+        }
+    }
+
+    /// Read data from the chip into the buffer.
+    pub fn read<'b>(self, buf: &'b mut [u8]) -> Instruction<'b> {
+        Instruction {
+            data: Data::Read(buf),
+            ..self.without_data()
+        }
+    }
+
+    /// Write data to the chip.
+    pub fn write<'b>(self, buf: &'b [u8]) -> Instruction<'b> {
+        Instruction {
+            data: Data::Write(buf),
+            ..self.without_data()
+        }
+    }
+
+    fn without_data<'b>(self) -> Instruction<'b> {
+        Instruction {
+            opcode: self.opcode,
+            opcode_lanes: self.opcode_lanes,
+            address: self.address,
+            address_lanes: self.address_lanes,
+            mode_bits: self.mode_bits,
+            dummy_cycles: self.dummy_cycles,
+            data: Data::None,
+            data_lanes: self.data_lanes,
+        }
+    }
+
+    /// The widest lane configuration used by any phase of the instruction.
+    pub fn max_lanes(&self) -> Lanes {
+        self.opcode_lanes
+            .max(self.address_lanes)
+            .max(self.data_lanes)
+    }
+
+    /// The opcode, address, mode bits and dummy bytes as they are sent on a single lane.
+    ///
+    /// Returns `None` if the instruction uses more than one lane or more than [Header::MAX_DUMMY_CYCLES] dummy cycles.
+    fn single_lane_header(&self) -> Option<Header> {
+        if self.max_lanes() != Lanes::Single || self.dummy_cycles > Header::MAX_DUMMY_CYCLES {
+            return None;
+        }
+
+        let mut header = Header {
+            bytes: [0; Header::CAPACITY],
+            len: 0,
+        };
+
+        header.push(&[self.opcode]);
+        if let Some(address) = self.address {
+            address.bytes().for_each(|byte| header.push(&[byte]));
+        }
+        if let Some(mode_bits) = self.mode_bits {
+            header.push(&[mode_bits]);
+        }
+        header.push(&[0; 8][..(self.dummy_cycles as usize).div_ceil(8)]);
+
+        Some(header)
+    }
+}
+
+/// The bytes that precede the data phase of an instruction on a single lane.
+struct Header {
+    bytes: [u8; Header::CAPACITY],
+    len: usize,
+}
+
+impl Header {
+    const CAPACITY: usize = 16;
+
+    /// The most dummy cycles that are sent on a single lane, as eight dummy bytes.
+    const MAX_DUMMY_CYCLES: u8 = 64;
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.bytes[self.len..][..bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// A bus that can execute the instructions of the chip.
+///
+/// Implement this for dual and quad SPI peripherals. The driver never issues instructions with more lanes than [Transport::MAX_LANES].
+pub trait Transport: ErrorType {
+    /// The maximum number of lanes the transport can use in any phase.
+    const MAX_LANES: Lanes;
+
+    /// Execute the instruction.
+    ///
+    /// Returns [Error::Unsupported] for an instruction the transport can't execute and [Error::SpiError] when the bus fails.
+    fn execute(&mut self, instruction: Instruction<'_>) -> Result<(), Error<Self::Error>>;
+}
+
+impl<SPI: embedded_hal::spi::SpiDevice> Transport for SPI {
+    const MAX_LANES: Lanes = Lanes::Single;
+
+    fn execute(&mut self, instruction: Instruction<'_>) -> Result<(), Error<Self::Error>> {
+        let header = instruction.single_lane_header().ok_or(Error::Unsupported)?;
+
+        let result =
+            match instruction.data {
+                Data::None => self.write(header.as_slice()),
+                Data::Read(buf) => self
+                    .transaction(&mut [Operation::Write(header.as_slice()), Operation::Read(buf)]),
+                Data::Write(buf) => self
+                    .transaction(&mut [Operation::Write(header.as_slice()), Operation::Write(buf)]),
+            };
+        result.map_err(Error::SpiError)
+    }
+}
+
+/// A bus that can execute the instructions of the chip asynchronously.
+///
+/// Implement this for dual and quad SPI peripherals. The driver never issues instructions with more lanes than [AsyncTransport::MAX_LANES].
+#[cfg(feature = "async")]
+#[allow(async_fn_in_trait)]
+pub trait AsyncTransport: ErrorType {
+    /// The maximum number of lanes the transport can use in any phase.
+    const MAX_LANES: Lanes;
+
+    /// Execute the instruction.
+    ///
+    /// Returns [Error::Unsupported] for an instruction the transport can't execute and [Error::SpiError] when the bus fails.
+    async fn execute(&mut self, instruction: Instruction<'_>) -> Result<(), Error<Self::Error>>;
+}
+
+#[cfg(feature = "async")]
+impl<SPI: embedded_hal_async::spi::SpiDevice> AsyncTransport for SPI {
+    const MAX_LANES: Lanes = Lanes::Single;
+
+    async fn execute(&mut self, instruction: Instruction<'_>) -> Result<(), Error<Self::Error>> {
+        let header = instruction.single_lane_header().ok_or(Error::Unsupported)?;
+
+        let result = match instruction.data {
+            Data::None => self.write(header.as_slice()).await,
+            Data::Read(buf) => {
+                self.transaction(&mut [Operation::Write(header.as_slice()), Operation::Read(buf)])
+                    .await
+            }
+            Data::Write(buf) => {
+                self.transaction(&mut [Operation::Write(header.as_slice()), Operation::Write(buf)])
+                    .await
+            }
+        };
+        result.map_err(Error::SpiError)
+    }
+}
@@ -1,111 +1,694 @@
 use super::*;
 use core::fmt::Debug;
+use core::ops::Range;
+use embedded_hal::delay::DelayNs;
 use embedded_hal::digital::OutputPin;
-use embedded_hal::spi::{Operation, SpiDevice};
 use embedded_storage::nor_flash::{MultiwriteNorFlash, NorFlash, ReadNorFlash};
+use protection::BlockProtection;
+use status::{StatusRegister1, StatusRegister2, StatusRegister3, Volatility};
+use transport::{Instruction, Lanes, Transport};
 
-impl<SPI, S: Debug, P: Debug, HOLD, WP> ReadNorFlash for W25q32jv<SPI, HOLD, WP>
+impl<SPI, S: Debug, HOLD, WP, DELAY> ReadNorFlash for W25q32jv<SPI, HOLD, WP, DELAY>
 where
-    SPI: SpiDevice<Error = S>,
-    HOLD: OutputPin<Error = P>,
-    WP: OutputPin<Error = P>,
+    SPI: Transport<Error = S>,
+    HOLD: OptionalPin,
+    WP: OptionalPin,
+    DELAY: PollDelay,
     S: Debug,
-    P: Debug,
 {
     const READ_SIZE: usize = 1;
 
-    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error<S, P>> {
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error<S>> {
         self.read(offset, bytes)
     }
 
     fn capacity(&self) -> usize {
-        Self::capacity()
+        self.geometry.capacity() as usize
     }
 }
 
-impl<SPI, S: Debug, P: Debug, HOLD, WP> NorFlash for W25q32jv<SPI, HOLD, WP>
+impl<SPI, S: Debug, HOLD, WP, DELAY> NorFlash for W25q32jv<SPI, HOLD, WP, DELAY>
 where
-    SPI: SpiDevice<Error = S>,
-    HOLD: OutputPin<Error = P>,
-    WP: OutputPin<Error = P>,
+    SPI: Transport<Error = S>,
+    HOLD: OptionalPin,
+    WP: OptionalPin,
+    DELAY: PollDelay,
     S: Debug,
-    P: Debug,
 {
     const WRITE_SIZE: usize = 1;
 
     const ERASE_SIZE: usize = SECTOR_SIZE as usize;
 
-    fn erase(&mut self, from: u32, to: u32) -> Result<(), Error<S, P>> {
-        self.erase_range(from, to)
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Error<S>> {
+        self.erase_range(from, to).map_err(|error| error.error)
     }
 
-    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error<S, P>> {
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error<S>> {
         self.write_blocking(offset, bytes)
     }
 }
 
-impl<SPI, S: Debug, P: Debug, HOLD, WP> MultiwriteNorFlash for W25q32jv<SPI, HOLD, WP>
+impl<SPI, S: Debug, HOLD, WP, DELAY> MultiwriteNorFlash for W25q32jv<SPI, HOLD, WP, DELAY>
 where
-    SPI: SpiDevice<Error = S>,
-    HOLD: OutputPin<Error = P>,
-    WP: OutputPin<Error = P>,
+    SPI: Transport<Error = S>,
+    HOLD: OptionalPin,
+    WP: OptionalPin,
+    DELAY: PollDelay,
     S: Debug,
-    P: Debug,
 {
 }
 
-impl<SPI, S: Debug, P: Debug, HOLD, WP> W25q32jv<SPI, HOLD, WP>
+impl<SPI, S: Debug, HOLD, WP> W25q32jv<SPI, HOLD, WP>
 where
-    SPI: SpiDevice<Error = S>,
-    HOLD: OutputPin<Error = P>,
-    WP: OutputPin<Error = P>,
+    SPI: Transport<Error = S>,
+    HOLD: OptionalPin,
+    WP: OptionalPin,
     S: Debug,
-    P: Debug,
 {
-    fn read_status_register(&mut self) -> Result<u8, Error<S, P>> {
-        let mut buf: [u8; 2] = [0; 2];
-        buf[0] = Command::ReadStatusRegister1 as u8;
+    /// Create a new driver and check that the chip on the bus is a Winbond chip with the given geometry.
+    ///
+    /// Returns [Error::UnexpectedChip] with the JEDEC ID that was read when this is not the case.
+    pub fn new_checked(
+        spi: SPI,
+        hold: HOLD,
+        wp: WP,
+        geometry: FlashGeometry,
+    ) -> Result<Self, Error<S>> {
+        let mut flash = Self::new(spi, hold, wp, geometry)?;
+
+        let id = flash.jedec_id()?;
+        if id.manufacturer != WINBOND_MANUFACTURER_ID
+            || id.capacity_bytes() != Some(geometry.capacity())
+        {
+            return Err(Error::UnexpectedChip(id));
+        }
+
+        Ok(flash)
+    }
+
+    /// Create a new driver with the geometry detected from the JEDEC ID of the chip on the bus.
+    ///
+    /// Returns [Error::UnexpectedChip] with the JEDEC ID that was read when it doesn't describe a supported geometry.
+    pub fn new_detected(spi: SPI, hold: HOLD, wp: WP) -> Result<Self, Error<S>> {
+        let mut flash = Self::new(spi, hold, wp, FlashGeometry::W25Q32)?;
+
+        let id = flash.jedec_id()?;
+        flash.geometry = FlashGeometry::from_jedec_id(&id).ok_or(Error::UnexpectedChip(id))?;
+
+        Ok(flash)
+    }
+}
+
+impl<SPI, S: Debug, HOLD, WP, DELAY> W25q32jv<SPI, HOLD, WP, DELAY>
+where
+    SPI: Transport<Error = S>,
+    HOLD: OptionalPin,
+    WP: OptionalPin,
+    DELAY: PollDelay,
+    S: Debug,
+{
+    fn read_status_register(&mut self, command: Command) -> Result<u8, Error<S>> {
+        let mut buf: [u8; 1] = [0; 1];
 
         self.spi
-            .transfer_in_place(&mut buf)
-            .map_err(Error::SpiError)?;
+            .execute(self.instruction(command as u8).read(&mut buf))?;
+
+        Ok(buf[0])
+    }
+
+    fn write_status_register(
+        &mut self,
+        command: Command,
+        bits: &[u8],
+        volatility: Volatility,
+    ) -> Result<(), Error<S>> {
+        if self.status_registers_locked()? {
+            return Err(Error::StatusRegisterLocked);
+        }
+
+        // The cached protection bits are read again after any status register write
+        self.block_protection = None;
+
+        match volatility {
+            Volatility::NonVolatile => self.enable_write()?,
+            // The volatile write enable doesn't set the WEL bit
+            Volatility::Volatile => {
+                self.spi
+                    .execute(self.instruction(Command::VolatileStatusRegisterWriteEnable as u8))?;
+            }
+        }
+
+        self.spi
+            .execute(self.instruction(command as u8).write(bits))?;
+
+        self.wait_busy(BusyOperation::StatusRegisterWrite)?;
 
-        Ok(buf[1])
+        Ok(())
+    }
+
+    /// Read status register 1, which holds the BUSY, WEL and block protection bits.
+    pub fn read_status_register_1(&mut self) -> Result<StatusRegister1, Error<S>> {
+        let bits = self.read_status_register(Command::ReadStatusRegister1)?;
+        Ok(StatusRegister1::from_bits(bits))
+    }
+
+    /// Read status register 2, which holds the SRL, QE, LB1-3, CMP and SUS bits.
+    pub fn read_status_register_2(&mut self) -> Result<StatusRegister2, Error<S>> {
+        let bits = self.read_status_register(Command::ReadStatusRegister2)?;
+        Ok(StatusRegister2::from_bits(bits))
+    }
+
+    /// Read status register 3, which holds the WPS and driver strength bits.
+    pub fn read_status_register_3(&mut self) -> Result<StatusRegister3, Error<S>> {
+        let bits = self.read_status_register(Command::ReadStatusRegister3)?;
+        Ok(StatusRegister3::from_bits(bits))
+    }
+
+    /// Write status register 1. The BUSY and WEL bits are read only and ignored.
+    pub fn write_status_register_1(
+        &mut self,
+        status: StatusRegister1,
+        volatility: Volatility,
+    ) -> Result<(), Error<S>> {
+        self.write_status_register(Command::WriteStatusRegister1, &[status.bits()], volatility)
+    }
+
+    /// Write status register 2. The SUS bit is read only and ignored, LB1-3 can only be set and never cleared again.
+    pub fn write_status_register_2(
+        &mut self,
+        status: StatusRegister2,
+        volatility: Volatility,
+    ) -> Result<(), Error<S>> {
+        self.write_status_register(Command::WriteStatusRegister2, &[status.bits()], volatility)?;
+        self.quad_enabled = status.qe;
+        Ok(())
+    }
+
+    /// Write status registers 1 and 2 in a single instruction, so they change at the same time.
+    fn write_status_registers_1_and_2(
+        &mut self,
+        status_1: StatusRegister1,
+        status_2: StatusRegister2,
+        volatility: Volatility,
+    ) -> Result<(), Error<S>> {
+        // Write Status Register-1 also writes status register 2 when a second byte is sent
+        self.write_status_register(
+            Command::WriteStatusRegister1,
+            &[status_1.bits(), status_2.bits()],
+            volatility,
+        )?;
+        self.quad_enabled = status_2.qe;
+        Ok(())
+    }
+
+    /// Write status register 3.
+    pub fn write_status_register_3(
+        &mut self,
+        status: StatusRegister3,
+        volatility: Volatility,
+    ) -> Result<(), Error<S>> {
+        self.write_status_register(Command::WriteStatusRegister3, &[status.bits()], volatility)?;
+        self.individual_block_locks = Some(status.wps);
+        Ok(())
+    }
+
+    /// Whether the individual block locks are used instead of the block protection bits (the WPS bit).
+    /// The bit is read from the chip once and then cached.
+    pub fn individual_block_locks(&mut self) -> Result<bool, Error<S>> {
+        if let Some(wps) = self.individual_block_locks {
+            return Ok(wps);
+        }
+
+        let wps = self.read_status_register_3()?.wps;
+        self.individual_block_locks = Some(wps);
+        Ok(wps)
+    }
+
+    fn block_lock_instruction(&mut self, command: Command, address: u32) -> Result<(), Error<S>> {
+        if address >= self.geometry.capacity() {
+            return Err(Error::OutOfBounds);
+        }
+
+        let (opcode, width) = self.prepare_address_instruction(command as u8)?;
+
+        self.enable_write()?;
+
+        self.spi
+            .execute(self.instruction(opcode).with_address(address, width))?;
+
+        Ok(())
+    }
+
+    /// Lock the sector or 64K block that contains the address, see [FlashGeometry::lock_unit].
+    /// The locks are volatile and only used while the WPS bit is set. All blocks are locked after a power up.
+    pub fn lock_block(&mut self, address: u32) -> Result<(), Error<S>> {
+        self.block_lock_instruction(Command::IndividualBlockLock, address)
+    }
+
+    /// Unlock the sector or 64K block that contains the address, see [FlashGeometry::lock_unit].
+    pub fn unlock_block(&mut self, address: u32) -> Result<(), Error<S>> {
+        self.block_lock_instruction(Command::IndividualBlockUnlock, address)
+    }
+
+    /// Whether the lock of the sector or 64K block that contains the address is set, see [FlashGeometry::lock_unit].
+    pub fn is_block_locked(&mut self, address: u32) -> Result<bool, Error<S>> {
+        if address >= self.geometry.capacity() {
+            return Err(Error::OutOfBounds);
+        }
+
+        let (opcode, width) = self.prepare_address_instruction(Command::ReadBlockLock as u8)?;
+        let mut buf: [u8; 1] = [0; 1];
+
+        self.spi.execute(
+            self.instruction(opcode)
+                .with_address(address, width)
+                .read(&mut buf),
+        )?;
+
+        Ok(buf[0] & 0x01 != 0)
+    }
+
+    /// Lock all sectors and blocks.
+    pub fn lock_all_blocks(&mut self) -> Result<(), Error<S>> {
+        self.enable_write()?;
+
+        self.spi
+            .execute(self.instruction(Command::GlobalBlockLock as u8))?;
+
+        Ok(())
+    }
+
+    /// Unlock all sectors and blocks.
+    pub fn unlock_all_blocks(&mut self) -> Result<(), Error<S>> {
+        self.enable_write()?;
+
+        self.spi
+            .execute(self.instruction(Command::GlobalBlockUnlock as u8))?;
+
+        Ok(())
+    }
+
+    /// Returns [Error::WriteProtected] if any part of the range is protected by the block protection bits
+    /// or, when the individual block locks are used, by a lock.
+    ///
+    /// When the block protection bits of the chip are not supported, the protected range is only known when all bits
+    /// are clear. Otherwise this returns [Error::Unsupported].
+    fn check_not_protected(&mut self, range: Range<u32>) -> Result<(), Error<S>> {
+        if !self.individual_block_locks()? {
+            if !BlockProtection::is_supported(self.geometry) {
+                if self.block_protection()? != BlockProtection::NONE {
+                    return Err(Error::Unsupported);
+                }
+                return Ok(());
+            }
+
+            let protected = self.protected_range()?;
+            if range.start < protected.end && protected.start < range.end {
+                return Err(Error::WriteProtected);
+            }
+
+            return Ok(());
+        }
+
+        let mut address = range.start;
+        while address < range.end {
+            if self.is_block_locked(address)? {
+                return Err(Error::WriteProtected);
+            }
+            address = self.geometry.lock_unit(address).end;
+        }
+
+        Ok(())
+    }
+
+    /// Protect exactly the given address range against writes and erases with the block protection bits.
+    /// An empty range removes the protection.
+    ///
+    /// Returns [Error::InvalidProtectionRange] if the range can't be represented by the bits,
+    /// and [Error::Unsupported] if the block protection bits of the chip are not supported.
+    /// The bits are ignored by the chip while the WPS bit is set.
+    pub fn protect_range(
+        &mut self,
+        range: Range<u32>,
+        volatility: Volatility,
+    ) -> Result<(), Error<S>> {
+        if !BlockProtection::is_supported(self.geometry) {
+            return Err(Error::Unsupported);
+        }
+        let protection = BlockProtection::from_range(range, self.geometry)
+            .ok_or(Error::InvalidProtectionRange)?;
+
+        let status_1 = self.read_status_register_1()?;
+        let status_2 = self.read_status_register_2()?;
+
+        // The BP, TB and SEC bits and the CMP bit are written together, so the chip is never left
+        // with a protection range that is made from the old and the new bits
+        self.write_status_registers_1_and_2(
+            StatusRegister1 {
+                bp: protection.bp,
+                tb: protection.tb,
+                sec: protection.sec,
+                ..status_1
+            },
+            StatusRegister2 {
+                cmp: protection.cmp,
+                ..status_2
+            },
+            volatility,
+        )
+    }
+
+    /// Read the address range that is protected by the block protection bits. An empty range means nothing is protected.
+    /// The bits are read from the chip once and then cached until the status registers are written.
+    ///
+    /// Returns [Error::Unsupported] if the block protection bits of the chip are not supported.
+    pub fn protected_range(&mut self) -> Result<Range<u32>, Error<S>> {
+        self.block_protection()?
+            .protected_range(self.geometry)
+            .ok_or(Error::Unsupported)
+    }
+
+    /// The BP, TB, SEC and CMP bits. They only change through the status register writes of the driver,
+    /// so they're read from the chip once and then cached.
+    fn block_protection(&mut self) -> Result<BlockProtection, Error<S>> {
+        if let Some(protection) = self.block_protection {
+            return Ok(protection);
+        }
+
+        let status_1 = self.read_status_register_1()?;
+        let status_2 = self.read_status_register_2()?;
+        let protection = BlockProtection {
+            bp: status_1.bp,
+            tb: status_1.tb,
+            sec: status_1.sec,
+            cmp: status_2.cmp,
+        };
+
+        self.block_protection = Some(protection);
+        Ok(protection)
+    }
+
+    /// Read how the status registers are protected against writes.
+    pub fn status_register_protection(&mut self) -> Result<StatusRegisterProtection, Error<S>> {
+        let status_1 = self.read_status_register_1()?;
+        let status_2 = self.read_status_register_2()?;
+
+        Ok(match (status_2.srl, status_1.srp) {
+            (false, false) => StatusRegisterProtection::Software,
+            (false, true) => StatusRegisterProtection::Hardware,
+            (true, false) => StatusRegisterProtection::PowerSupplyLockDown,
+            (true, true) => StatusRegisterProtection::OneTimeProgram,
+        })
+    }
+
+    /// Whether the status registers can't be written because of the SRP and SRL bits and the WP pin.
+    /// The WP pin has no function while the QE bit is set.
+    fn status_registers_locked(&mut self) -> Result<bool, Error<S>> {
+        let status_1 = self.read_status_register_1()?;
+        let status_2 = self.read_status_register_2()?;
+
+        Ok(status_2.srl || (status_1.srp && self.wp_state == PinState::Low && !status_2.qe))
     }
 
     /// The flash chip is unable to perform new commands while it is still working on a previous one. Especially erases take a long time.
     /// This function returns true while the chip is unable to respond to commands (with the exception of the busy command).
-    fn busy(&mut self) -> Result<bool, Error<S, P>> {
-        Ok((self.read_status_register()? & 0x01) != 0)
+    fn busy(&mut self) -> Result<bool, Error<S>> {
+        Ok(self.read_status_register_1()?.busy)
     }
 
-    fn write_enabled(&mut self) -> Result<bool, Error<S, P>> {
-        Ok((self.read_status_register()? & 0x02) != 0)
+    /// Waits until the chip is no longer busy, polling the status at an interval suited to the operation.
+    /// Returns [Error::Timeout] when the operation takes longer than its datasheet maximum plus the timeout margin.
+    fn wait_busy(&mut self, operation: BusyOperation) -> Result<(), Error<S>> {
+        let timeout = self.timeout_us(operation);
+        let interval = operation.poll_interval_us();
+        let mut elapsed: u64 = 0;
+
+        while self.busy()? {
+            if timeout.is_some_and(|timeout| elapsed > timeout) {
+                return Err(Error::Timeout);
+            }
+
+            self.delay.wait_us(interval);
+            elapsed += interval as u64;
+        }
+
+        Ok(())
+    }
+
+    fn write_enabled(&mut self) -> Result<bool, Error<S>> {
+        Ok(self.read_status_register_1()?.wel)
     }
 
     /// Request the 64 bit id that is unique to this chip.
-    pub fn device_id(&mut self) -> Result<[u8; 8], Error<S, P>> {
-        let mut buf: [u8; 13] = [0; 13];
-        buf[0] = Command::UniqueId as u8;
+    /// Not available in QPI mode.
+    pub fn device_id(&mut self) -> Result<[u8; 8], Error<S>> {
+        self.check_not_qpi_mode()?;
+
+        let mut buf: [u8; 8] = [0; 8];
+
+        self.spi.execute(
+            self.instruction(Command::UniqueId as u8)
+                .with_dummy_cycles(32)
+                .read(&mut buf),
+        )?;
+
+        Ok(buf)
+    }
+
+    /// Request the JEDEC manufacturer, memory type and capacity ID of the chip.
+    pub fn jedec_id(&mut self) -> Result<JedecId, Error<S>> {
+        let mut buf: [u8; 3] = [0; 3];
 
         self.spi
-            .transfer_in_place(&mut buf)
-            .map_err(Error::SpiError)?;
+            .execute(self.instruction(Command::JedecId as u8).read(&mut buf))?;
+
+        Ok(JedecId {
+            manufacturer: buf[0],
+            memory_type: buf[1],
+            capacity: buf[2],
+        })
+    }
+
+    /// Request the manufacturer and device ID of the chip.
+    pub fn manufacturer_device_id(&mut self) -> Result<ManufacturerDeviceId, Error<S>> {
+        let mut buf: [u8; 2] = [0; 2];
+
+        self.spi.execute(
+            self.instruction(Command::ManufacturerDeviceId as u8)
+                .with_address(0, AddressWidth::ThreeByte)
+                .read(&mut buf),
+        )?;
+
+        Ok(ManufacturerDeviceId {
+            manufacturer: buf[0],
+            device: buf[1],
+        })
+    }
+
+    /// Reads a chunk of the SFDP data of the chip. Not available in QPI mode.
+    /// The first byte is read from the provided SFDP address. This address is then incremented for each following byte.
+    ///
+    /// # Arguments
+    /// * `address` - SFDP address where the first byte of the buf will be read.
+    /// * `buf` - Slice that is going to be filled with the read bytes.
+    pub fn read_sfdp(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error<S>> {
+        self.check_not_qpi_mode()?;
+
+        self.spi.execute(
+            self.instruction(Command::ReadSfdp as u8)
+                .with_address(address, AddressWidth::ThreeByte)
+                .with_dummy_cycles(8)
+                .read(buf),
+        )?;
+
+        Ok(())
+    }
+
+    /// Read and parse the JEDEC Basic Flash Parameter Table from the SFDP data of the chip.
+    pub fn read_basic_flash_parameters(&mut self) -> Result<sfdp::BasicFlashParameters, Error<S>> {
+        let mut buf = [0; sfdp::HEADER_SIZE];
+        self.read_sfdp(0, &mut buf)?;
+        let header = sfdp::SfdpHeader::parse(&buf).ok_or(Error::InvalidSfdp)?;
+
+        let mut basic_table = None;
+        for index in 0..header.n_parameter_headers {
+            self.read_sfdp(sfdp::SfdpHeader::parameter_header_address(index), &mut buf)?;
+            let parameter_header = sfdp::ParameterHeader::parse(&buf);
+
+            if parameter_header.is_newer_basic_table(basic_table.as_ref()) {
+                basic_table = Some(parameter_header);
+            }
+        }
+        let basic_table = basic_table.ok_or(Error::InvalidSfdp)?;
+
+        let mut buf = [0; sfdp::MAX_BASIC_FLASH_PARAMETER_DWORDS * 4];
+        let buf = &mut buf[..basic_table.read_length()];
+        self.read_sfdp(basic_table.table_pointer, buf)?;
+
+        sfdp::BasicFlashParameters::parse_bytes(buf).ok_or(Error::InvalidSfdp)
+    }
+
+    /// Configure the geometry and the erase and fast read instructions of the driver from the SFDP data of the chip.
+    /// This makes it possible to use compatible chips without knowing their size and instructions up front.
+    ///
+    /// Returns the parsed parameters, or [Error::Unsupported] if the chip can't be used by this driver.
+    pub fn configure_from_sfdp(&mut self) -> Result<sfdp::BasicFlashParameters, Error<S>> {
+        let parameters = self.read_basic_flash_parameters()?;
+
+        if parameters
+            .page_size
+            .is_some_and(|page_size| page_size != PAGE_SIZE)
+        {
+            return Err(Error::Unsupported);
+        }
+
+        let geometry = u32::try_from(parameters.capacity)
+            .ok()
+            .and_then(FlashGeometry::from_capacity)
+            .ok_or(Error::Unsupported)?;
+        if geometry.address_width() == AddressWidth::FourByte
+            && parameters.address_bytes == sfdp::AddressBytes::ThreeByteOnly
+        {
+            return Err(Error::Unsupported);
+        }
+
+        // Nothing is changed until all the parameters are known to be usable
+        self.geometry = geometry;
+        self.erase_opcodes = EraseOpcodes::from_sfdp(&parameters);
+        self.read_opcodes = ReadOpcodes::from_sfdp(&parameters);
 
-        Ok(TryFrom::try_from(&buf[5..]).unwrap())
+        Ok(parameters)
     }
 
     /// Reset the chip
-    pub fn reset(&mut self) -> Result<(), Error<S, P>> {
+    ///
+    /// On a quad transport the chip is taken out of QPI mode first, even if the driver doesn't know it's in QPI mode,
+    /// so the reset instructions are always understood.
+    pub fn reset(&mut self) -> Result<(), Error<S>> {
+        if SPI::MAX_LANES >= Lanes::Quad {
+            self.spi
+                .execute(Instruction::new(Command::ExitQpiMode as u8).with_lanes(
+                    Lanes::Quad,
+                    Lanes::Quad,
+                    Lanes::Quad,
+                ))?;
+            self.qpi_mode = false;
+        }
+
+        self.spi
+            .execute(self.instruction(Command::EnableReset as u8))?;
+        self.spi.execute(self.instruction(Command::Reset as u8))?;
+        self.four_byte_address_mode = false;
+        self.qpi_mode = false;
+        self.read_parameters = ReadParameters::default();
+        self.individual_block_locks = None;
+        self.block_protection = None;
+        Ok(())
+    }
+
+    /// Put the chip in QPI mode, in which all instructions are sent on four lanes.
+    ///
+    /// This sets the QE bit first. Returns [Error::Unsupported] if the transport doesn't have four lanes
+    /// or the chip doesn't respond the same in QPI mode.
+    /// The read parameters are reset to their defaults, as the chip does.
+    pub fn enter_qpi_mode(&mut self) -> Result<(), Error<S>> {
+        if SPI::MAX_LANES < Lanes::Quad {
+            return Err(Error::Unsupported);
+        }
+        if self.qpi_mode {
+            return Ok(());
+        }
+
+        let jedec_id = self.jedec_id()?;
+        self.enable_quad()?;
+
+        self.spi
+            .execute(Instruction::new(Command::EnterQpiMode as u8))?;
+        self.qpi_mode = true;
+        self.read_parameters = ReadParameters::default();
+
+        // Chips without QPI mode ignore the instruction
+        if self.jedec_id()? != jedec_id {
+            self.exit_qpi_mode()?;
+            return Err(Error::Unsupported);
+        }
+
+        Ok(())
+    }
+
+    /// Take the chip out of QPI mode.
+    pub fn exit_qpi_mode(&mut self) -> Result<(), Error<S>> {
+        if !self.qpi_mode {
+            return Ok(());
+        }
+
+        self.spi
+            .execute(self.instruction(Command::ExitQpiMode as u8))?;
+        self.qpi_mode = false;
+
+        Ok(())
+    }
+
+    /// Set the dummy clocks and wrap length that are used in QPI mode.
+    ///
+    /// Returns [Error::Unsupported] if the chip isn't in QPI mode.
+    pub fn set_read_parameters(&mut self, parameters: ReadParameters) -> Result<(), Error<S>> {
+        if !self.qpi_mode {
+            return Err(Error::Unsupported);
+        }
+
+        self.spi.execute(
+            self.instruction(Command::SetReadParameters as u8)
+                .write(&[parameters.bits()]),
+        )?;
+        self.read_parameters = parameters;
+
+        Ok(())
+    }
+
+    /// Puts the chip into 4-byte address mode, in which all instructions take 4-byte addresses.
+    /// Only chips larger than 128 M-bit support this mode.
+    ///
+    /// The driver uses the dedicated 4-byte instructions where possible and enters this mode by itself when needed,
+    /// so this is only useful when the mode is required by something else than this driver.
+    pub fn enter_4_byte_address_mode(&mut self) -> Result<(), Error<S>> {
+        if self.geometry.address_width() != AddressWidth::FourByte {
+            return Err(Error::Unsupported);
+        }
+
         self.spi
-            .write(&[Command::EnableReset as u8])
-            .map_err(Error::SpiError)?;
+            .execute(self.instruction(Command::Enter4ByteAddressMode as u8))?;
+        self.four_byte_address_mode = true;
+
+        Ok(())
+    }
+
+    /// Returns the chip to 3-byte address mode, for example before handing the chip over to a bootloader.
+    /// The driver keeps using the dedicated 4-byte instructions afterwards.
+    pub fn exit_4_byte_address_mode(&mut self) -> Result<(), Error<S>> {
+        if self.geometry.address_width() != AddressWidth::FourByte {
+            return Err(Error::Unsupported);
+        }
+
         self.spi
-            .write(&[Command::Reset as u8])
-            .map_err(Error::SpiError)?;
+            .execute(self.instruction(Command::Exit4ByteAddressMode as u8))?;
+        self.four_byte_address_mode = false;
+
         Ok(())
     }
 
+    /// Get the instruction and address width for an instruction that takes a memory address.
+    /// The chip is put in 4-byte address mode when a large chip has no dedicated 4-byte variant of the instruction.
+    fn prepare_address_instruction(&mut self, opcode: u8) -> Result<(u8, AddressWidth), Error<S>> {
+        match self.address_instruction(opcode) {
+            Some(instruction) => Ok(instruction),
+            None => {
+                self.enter_4_byte_address_mode()?;
+                Ok((opcode, AddressWidth::FourByte))
+            }
+        }
+    }
+
     /// Reads a chunk of bytes from the flash chip.
     /// The number of bytes read is equal to the length of the buf slice.
     /// The first byte is read from the provided address. This address is then incremented for each following byte.
@@ -113,17 +696,23 @@ where
     /// # Arguments
     /// * `address` - Address where the first byte of the buf will be read.
     /// * `buf` - Slice that is going to be filled with the read bytes.
-    pub fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error<S, P>> {
-        if address + buf.len() as u32 > CAPACITY {
-            return Err(Error::OutOfBounds);
+    pub fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error<S>> {
+        checked_range(address, buf.len(), self.geometry.capacity()).ok_or(Error::OutOfBounds)?;
+
+        let instruction = self.read_instruction(SPI::MAX_LANES)?;
+        if instruction.data_lanes == Lanes::Quad {
+            self.enable_quad()?;
         }
+        let (opcode, width) = self.prepare_address_instruction(instruction.opcode)?;
 
-        self.spi
-            .transaction(&mut [
-                Operation::Write(&command_and_address(Command::ReadData as u8, address)),
-                Operation::Read(buf),
-            ])
-            .map_err(Error::SpiError)?;
+        self.spi.execute(
+            Instruction {
+                opcode,
+                ..instruction
+            }
+            .with_address(address, width)
+            .read(buf),
+        )?;
 
         Ok(())
     }
@@ -131,10 +720,9 @@ where
     /// Sets the enable_write flag on the flash chip to true.
     /// Writes and erases to the chip only have effect when this flag is true.
     /// Each write and erase clears the flag, requiring it to be set to true again for the next command.
-    fn enable_write(&mut self) -> Result<(), Error<S, P>> {
+    fn enable_write(&mut self) -> Result<(), Error<S>> {
         self.spi
-            .write(&[Command::WriteEnable as u8])
-            .map_err(Error::SpiError)?;
+            .execute(self.instruction(Command::WriteEnable as u8))?;
 
         if !self.write_enabled()? {
             return Err(Error::WriteEnableFail);
@@ -143,16 +731,38 @@ where
         Ok(())
     }
 
+    /// Sets the Quad Enable bit in status register 2 if it isn't set yet.
+    /// The quad read and write instructions only work when this bit is set.
+    fn enable_quad(&mut self) -> Result<(), Error<S>> {
+        if self.quad_enabled {
+            return Ok(());
+        }
+
+        let status = self.read_status_register_2()?;
+
+        if !status.qe {
+            self.write_status_register_2(
+                StatusRegister2 { qe: true, ..status },
+                Volatility::NonVolatile,
+            )?;
+        }
+
+        self.quad_enabled = true;
+
+        Ok(())
+    }
+
     /// Writes a chunk of bytes to the flash chip.
     /// The first byte is written to the provided address. This address is then incremented for each following byte.
+    /// On a quad transport the pages are written with Quad Input Page Program, after setting the Quad Enable bit.
     ///
     /// # Arguments
     /// * `address` - Address where the first byte of the buf will be written.
     /// * `buf` - Slice of bytes that will be written.
-    pub fn write_blocking(&mut self, mut address: u32, mut buf: &[u8]) -> Result<(), Error<S, P>> {
-        if address + buf.len() as u32 > CAPACITY {
-            return Err(Error::OutOfBounds);
-        }
+    pub fn write_blocking(&mut self, mut address: u32, mut buf: &[u8]) -> Result<(), Error<S>> {
+        let range = checked_range(address, buf.len(), self.geometry.capacity())
+            .ok_or(Error::OutOfBounds)?;
+        self.check_not_protected(range)?;
 
         // Write first chunk, taking into account that given addres might
         // point to a location that is not on a page boundary,
@@ -176,31 +786,102 @@ where
     }
 
     /// Execute a write on a single page
-    fn write_page(&mut self, address: u32, buf: &[u8]) -> Result<(), Error<S, P>> {
+    fn write_page(&mut self, address: u32, buf: &[u8]) -> Result<(), Error<S>> {
+        let operation = self.send_page_program(address, buf)?;
+        self.finish(operation)
+    }
+
+    /// Starts a write on a single page and returns without waiting for the write to finish.
+    /// Use [Self::poll] or [Self::is_done] to find out when the write is done.
+    ///
+    /// The chip ignores all other instructions that access the memory until the write is done.
+    ///
+    /// # Arguments
+    /// * `address` - Address where the first byte of the buf will be written.
+    /// * `buf` - Slice of bytes that will be written. The bytes may not cross a page boundary.
+    pub fn start_write_page<'b>(
+        &mut self,
+        address: u32,
+        buf: &'b [u8],
+    ) -> Result<PendingOperation<'b>, Error<S>> {
+        let range = checked_range(address, buf.len(), self.geometry.capacity())
+            .ok_or(Error::OutOfBounds)?;
+        self.check_not_protected(range)?;
+
+        self.send_page_program(address, buf)
+    }
+
+    /// Sends the program instruction of a single page, without waiting for the write to finish.
+    fn send_page_program<'b>(
+        &mut self,
+        address: u32,
+        buf: &'b [u8],
+    ) -> Result<PendingOperation<'b>, Error<S>> {
         // We don't support wrapping writes. They're scary
         if (address & 0x000000FF) + buf.len() as u32 > PAGE_SIZE {
             return Err(Error::OutOfBounds);
         }
 
+        // Use Quad Input Page Program when the transport has enough lanes.
+        // In QPI mode the normal Page Program is already sent entirely on four lanes.
+        let (command, address_lanes, data_lanes) = if self.qpi_mode {
+            (Command::PageProgram, Lanes::Quad, Lanes::Quad)
+        } else if SPI::MAX_LANES >= Lanes::Quad {
+            self.enable_quad()?;
+            (Command::QuadPageProgram, Lanes::Single, Lanes::Quad)
+        } else {
+            (Command::PageProgram, Lanes::Single, Lanes::Single)
+        };
+        let (opcode, width) = self.prepare_address_instruction(command as u8)?;
+
         self.enable_write()?;
 
-        self.spi
-            .transaction(&mut [
-                Operation::Write(&command_and_address(Command::PageProgram as u8, address)),
-                Operation::Write(buf),
-            ])
-            .map_err(Error::SpiError)?;
+        self.spi.execute(
+            Instruction::new(opcode)
+                .with_lanes(address_lanes, address_lanes, data_lanes)
+                .with_address(address, width)
+                .write(buf),
+        )?;
 
-        while self.busy()? {}
+        Ok(PendingOperation::program(address, buf))
+    }
 
+    /// Whether the chip has finished the erase or program that is in progress.
+    pub fn is_done(&mut self) -> Result<bool, Error<S>> {
+        Ok(!self.busy()?)
+    }
+
+    /// Checks whether the started erase or program is done.
+    /// Returns `true` once it's done, after verifying the result when the `readback-check` feature is enabled.
+    pub fn poll(&mut self, operation: &PendingOperation<'_>) -> Result<bool, Error<S>> {
+        if self.busy()? {
+            return Ok(false);
+        }
+
+        self.verify(operation)?;
+
+        Ok(true)
+    }
+
+    /// Waits until the started erase or program is done.
+    fn finish(&mut self, operation: PendingOperation<'_>) -> Result<(), Error<S>> {
+        self.wait_busy(operation.kind)?;
+        self.verify(&operation)
+    }
+
+    /// Checks the result of a finished erase or program when the `readback-check` feature is enabled.
+    fn verify(&mut self, operation: &PendingOperation<'_>) -> Result<(), Error<S>> {
         if cfg!(feature = "readback-check") {
-            self.readback_check(address, buf)?;
+            match operation.data {
+                Some(data) => self.readback_check(operation.address, data)?,
+                None => self.erased_readback_check(operation.address, operation.len)?,
+            }
         }
 
         Ok(())
     }
 
-    fn readback_check(&mut self, mut address: u32, data: &[u8]) -> Result<(), Error<S, P>> {
+    fn readback_check(&mut self, mut address: u32, data: &[u8]) -> Result<(), Error<S>> {
         const CHUNK_SIZE: usize = 64;
 
         let mut buf = [0; CHUNK_SIZE];
@@ -218,158 +899,497 @@ where
         Ok(())
     }
 
-    /// Erases a range of sectors. The range is expressed in bytes. These bytes need to be a multiple of SECTOR_SIZE.
-    /// If the range starts at SECTOR_SIZE * 3 then the erase starts at the fourth sector.
-    /// All sectors are erased in the range [start_sector..end_sector].
-    /// The start address may not be a higher value than the end address.
+    /// Reads a chunk of a security register. Not available in QPI mode.
     ///
     /// # Arguments
-    /// * `start_address` - Address of the first byte of the start of the range of sectors that need to be erased.
-    /// * `end_address` - Address of the first byte of the end of the range of sectors that need to be erased.
-    pub fn erase_range(&mut self, start_address: u32, end_address: u32) -> Result<(), Error<S, P>> {
-        if start_address % (SECTOR_SIZE) != 0 {
-            return Err(Error::NotAligned);
-        }
+    /// * `register` - The security register to read from.
+    /// * `offset` - Offset in the register of the first byte of the buf.
+    /// * `buf` - Slice that is going to be filled with the read bytes.
+    pub fn read_security_register(
+        &mut self,
+        register: SecurityRegister,
+        offset: u32,
+        buf: &mut [u8],
+    ) -> Result<(), Error<S>> {
+        checked_range(offset, buf.len(), SECURITY_REGISTER_SIZE).ok_or(Error::OutOfBounds)?;
+        self.check_not_qpi_mode()?;
+
+        let (opcode, width) =
+            self.prepare_address_instruction(Command::ReadSecurityRegister as u8)?;
+
+        self.spi.execute(
+            self.instruction(opcode)
+                .with_address(register.address() + offset, width)
+                .with_dummy_cycles(8)
+                .read(buf),
+        )?;
 
-        if end_address % (SECTOR_SIZE) != 0 {
-            return Err(Error::NotAligned);
-        }
+        Ok(())
+    }
 
-        if start_address > end_address {
-            return Err(Error::OutOfBounds);
+    /// Programs a chunk of a security register. Returns [Error::WriteProtected] if the register is locked.
+    ///
+    /// # Arguments
+    /// * `register` - The security register to program.
+    /// * `offset` - Offset in the register where the first byte of the buf will be written.
+    /// * `buf` - Slice of bytes that will be written.
+    pub fn program_security_register(
+        &mut self,
+        register: SecurityRegister,
+        offset: u32,
+        buf: &[u8],
+    ) -> Result<(), Error<S>> {
+        checked_range(offset, buf.len(), SECURITY_REGISTER_SIZE).ok_or(Error::OutOfBounds)?;
+        self.check_not_qpi_mode()?;
+        if self.is_security_register_locked(register)? {
+            return Err(Error::WriteProtected);
         }
 
-        let start_sector = start_address / SECTOR_SIZE;
-        let end_sector = end_address / SECTOR_SIZE;
+        let (opcode, width) =
+            self.prepare_address_instruction(Command::ProgramSecurityRegister as u8)?;
 
-        for sector in start_sector..end_sector {
-            self.erase_sector(sector).unwrap();
+        self.enable_write()?;
+
+        self.spi.execute(
+            self.instruction(opcode)
+                .with_address(register.address() + offset, width)
+                .write(buf),
+        )?;
+
+        self.wait_busy(BusyOperation::PageProgram)?;
+
+        if cfg!(feature = "readback-check") {
+            self.security_register_readback_check(register, offset, buf)?;
         }
 
         Ok(())
     }
 
-    /// Erases a single sector of flash memory with the size of SECTOR_SIZE.
-    ///
-    /// # Arguments
-    /// * `index` - the index of the sector that needs to be erased. The address of the first byte of the sector is the provided index * SECTOR_SIZE.
-    pub fn erase_sector(&mut self, index: u32) -> Result<(), Error<S, P>> {
-        if index >= N_SECTORS {
-            return Err(Error::OutOfBounds);
+    /// Erases a whole security register. Returns [Error::WriteProtected] if the register is locked.
+    pub fn erase_security_register(&mut self, register: SecurityRegister) -> Result<(), Error<S>> {
+        self.check_not_qpi_mode()?;
+        if self.is_security_register_locked(register)? {
+            return Err(Error::WriteProtected);
         }
 
-        self.enable_write()?;
+        let (opcode, width) =
+            self.prepare_address_instruction(Command::EraseSecurityRegister as u8)?;
 
-        let address: u32 = index * SECTOR_SIZE;
+        self.enable_write()?;
 
-        self.spi
-            .write(&command_and_address(Command::SectorErase as u8, address))
-            .map_err(Error::SpiError)?;
+        self.spi.execute(
+            self.instruction(opcode)
+                .with_address(register.address(), width),
+        )?;
 
-        while self.busy()? {}
+        self.wait_busy(BusyOperation::SectorErase)?;
 
         if cfg!(feature = "readback-check") {
-            for offset in (0..SECTOR_SIZE).step_by(64) {
-                self.readback_check(address + offset, &[0xFF; 64])?;
-            }
+            self.security_register_readback_check(
+                register,
+                0,
+                &[0xFF; SECURITY_REGISTER_SIZE as usize],
+            )?;
         }
 
         Ok(())
     }
 
-    /// Erases a single block of flash memory with the size of BLOCK_32K_SIZE.
-    ///
-    /// # Arguments
-    /// * `index` - the index of the block that needs to be erased. The address of the first byte of the block is the provided index * BLOCK_32K_SIZE.
-    pub fn erase_block_32k(&mut self, index: u32) -> Result<(), Error<S, P>> {
-        if index >= N_BLOCKS_32K {
-            return Err(Error::OutOfBounds);
-        }
+    /// Whether the lock bit (LB1-3) of the security register is set.
+    pub fn is_security_register_locked(
+        &mut self,
+        register: SecurityRegister,
+    ) -> Result<bool, Error<S>> {
+        let status = self.read_status_register_2()?;
+
+        Ok(match register {
+            SecurityRegister::One => status.lb1,
+            SecurityRegister::Two => status.lb2,
+            SecurityRegister::Three => status.lb3,
+        })
+    }
 
-        self.enable_write()?;
+    /// Permanently locks the security register by setting its lock bit (LB1-3).
+    /// The register can never be programmed or erased again, there is no way to undo this.
+    pub fn lock_security_register(
+        &mut self,
+        register: SecurityRegister,
+        _confirmation: PermanentLock,
+    ) -> Result<(), Error<S>> {
+        let status = self.read_status_register_2()?;
+        let status = match register {
+            SecurityRegister::One => StatusRegister2 {
+                lb1: true,
+                ..status
+            },
+            SecurityRegister::Two => StatusRegister2 {
+                lb2: true,
+                ..status
+            },
+            SecurityRegister::Three => StatusRegister2 {
+                lb3: true,
+                ..status
+            },
+        };
+
+        self.write_status_register_2(status, Volatility::NonVolatile)
+    }
 
-        let address: u32 = index * BLOCK_32K_SIZE;
+    fn security_register_readback_check(
+        &mut self,
+        register: SecurityRegister,
+        mut offset: u32,
+        data: &[u8],
+    ) -> Result<(), Error<S>> {
+        const CHUNK_SIZE: usize = 64;
 
-        self.spi
-            .write(&command_and_address(Command::Block32Erase as u8, address))
-            .map_err(Error::SpiError)?;
+        let mut buf = [0; CHUNK_SIZE];
 
-        while self.busy()? {}
+        for chunk in data.chunks(CHUNK_SIZE) {
+            let buf = &mut buf[..chunk.len()];
+            self.read_security_register(register, offset, buf)?;
+            offset += CHUNK_SIZE as u32;
 
-        if cfg!(feature = "readback-check") {
-            for offset in (0..BLOCK_32K_SIZE).step_by(64) {
-                self.readback_check(address + offset, &[0xFF; 64])?;
+            if buf != chunk {
+                return Err(Error::ReadbackFail);
             }
         }
 
         Ok(())
     }
 
-    /// Erases a single block of flash memory with the size of BLOCK_64K_SIZE.
+    /// Erases a range of sectors. The range is expressed in bytes. These bytes need to be a multiple of SECTOR_SIZE.
+    /// If the range starts at SECTOR_SIZE * 3 then the erase starts at the fourth sector.
+    /// All sectors are erased in the range [start_sector..end_sector].
+    /// The start address may not be a higher value than the end address.
+    ///
+    /// The range is erased with the largest aligned 64K blocks, 32K blocks and sectors that fit,
+    /// and with a chip erase when the range covers the whole chip and blank erases aren't skipped.
+    ///
+    /// Returns [Error::WriteProtected] before anything is erased when any part of the range is protected.
+    /// On an error the returned [EraseRangeError] contains the address where the erase failed
+    /// and the number of sectors that were erased before it, so the erase can be resumed from there.
     ///
     /// # Arguments
-    /// * `index` - the index of the block that needs to be erased. The address of the first byte of the block is the provided index * BLOCK_64K_SIZE.
-    pub fn erase_block_64k(&mut self, index: u32) -> Result<(), Error<S, P>> {
-        if index >= N_BLOCKS_64K {
-            return Err(Error::OutOfBounds);
+    /// * `start_address` - Address of the first byte of the start of the range of sectors that need to be erased.
+    /// * `end_address` - Address of the first byte of the end of the range of sectors that need to be erased.
+    pub fn erase_range(
+        &mut self,
+        start_address: u32,
+        end_address: u32,
+    ) -> Result<(), EraseRangeError<S>> {
+        let fail = |error, address| EraseRangeError::new(error, start_address, address);
+
+        if !start_address.is_multiple_of(SECTOR_SIZE) {
+            return Err(fail(Error::NotAligned, start_address));
         }
 
-        self.enable_write()?;
-
-        let address: u32 = index * BLOCK_64K_SIZE;
+        if !end_address.is_multiple_of(SECTOR_SIZE) {
+            return Err(EraseRangeError::rejected(Error::NotAligned, end_address));
+        }
 
-        self.spi
-            .write(&command_and_address(Command::Block64Erase as u8, address))
-            .map_err(Error::SpiError)?;
+        if start_address > end_address || end_address > self.geometry.capacity() {
+            return Err(fail(Error::OutOfBounds, start_address));
+        }
 
-        while self.busy()? {}
+        // The whole range is checked once, instead of before every erase
+        self.check_not_protected(start_address..end_address)
+            .map_err(|error| fail(error, start_address))?;
+
+        if start_address == 0 && end_address == self.geometry.capacity() && !self.skip_blank_erases
+        {
+            let operation = self
+                .send_chip_erase()
+                .map_err(|error| fail(error, start_address))?;
+            return self
+                .finish(operation)
+                .map_err(|error| fail(error, start_address));
+        }
 
-        if cfg!(feature = "readback-check") {
-            for offset in (0..BLOCK_64K_SIZE).step_by(64) {
-                self.readback_check(address + offset, &[0xFF; 64])?;
-            }
+        let mut address = start_address;
+        while address < end_address {
+            let unit = self.largest_erase_unit(address, end_address - address);
+            self.erase_unit(unit, address)
+                .map_err(|error| fail(error, address))?;
+            address += unit.size();
         }
 
         Ok(())
     }
 
-    /// Erases all sectors on the flash chip.
-    /// This is a very expensive operation.
-    pub fn erase_chip(&mut self) -> Result<(), Error<S, P>> {
+    /// Checks the index and the protection and sends the erase instruction, without waiting for the erase to finish.
+    fn send_erase(
+        &mut self,
+        unit: EraseUnit,
+        index: u32,
+    ) -> Result<PendingOperation<'static>, Error<S>> {
+        let range = self.erase_unit_range(unit, index)?;
+        self.check_not_protected(range.clone())?;
+
+        self.send_erase_instruction(unit, range.start)
+    }
+
+    /// Sends the erase instruction of the unit at the address, which must be in bounds and not protected.
+    fn send_erase_instruction(
+        &mut self,
+        unit: EraseUnit,
+        address: u32,
+    ) -> Result<PendingOperation<'static>, Error<S>> {
+        let opcode = self.erase_opcodes.opcode(unit).ok_or(Error::Unsupported)?;
+        let (opcode, width) = self.prepare_address_instruction(opcode)?;
+
         self.enable_write()?;
 
         self.spi
-            .write(&[Command::ChipErase as u8])
-            .map_err(Error::SpiError)?;
+            .execute(self.instruction(opcode).with_address(address, width))?;
 
-        while self.busy()? {}
+        Ok(PendingOperation::erase(
+            address,
+            unit.size(),
+            unit.busy_operation(),
+        ))
+    }
 
-        if cfg!(feature = "readback-check") {
-            for address in (0..CAPACITY).step_by(64) {
-                self.readback_check(address, &[0xFF; 64])?;
+    /// Checks the index and the protection before the blank check, so a protected unit is never reported as skipped.
+    fn erase(&mut self, unit: EraseUnit, index: u32) -> Result<(), Error<S>> {
+        let range = self.erase_unit_range(unit, index)?;
+        self.check_not_protected(range.clone())?;
+
+        self.erase_unit(unit, range.start)
+    }
+
+    /// Erases the unit at the address, which must be in bounds and not protected,
+    /// unless it's blank and blank erases are skipped.
+    fn erase_unit(&mut self, unit: EraseUnit, address: u32) -> Result<(), Error<S>> {
+        if self.skip_blank_erases && self.is_blank(address, unit.size())? {
+            self.erase_counters.skipped = self.erase_counters.skipped.saturating_add(1);
+            return Ok(());
+        }
+
+        let operation = self.send_erase_instruction(unit, address)?;
+        self.erase_counters.erased = self.erase_counters.erased.saturating_add(1);
+        self.finish(operation)
+    }
+
+    /// Whether all bytes in the range are 0xFF. Reads with the configured read mode, like [Self::read].
+    fn is_blank(&mut self, mut address: u32, size: u32) -> Result<bool, Error<S>> {
+        const CHUNK_SIZE: u32 = 64;
+
+        let mut buf = [0; CHUNK_SIZE as usize];
+        let end = address.checked_add(size).ok_or(Error::OutOfBounds)?;
+
+        while address < end {
+            let buf = &mut buf[..CHUNK_SIZE.min(end - address) as usize];
+            self.read(address, buf)?;
+            address += CHUNK_SIZE;
+
+            if buf.iter().any(|byte| *byte != 0xFF) {
+                return Ok(false);
             }
         }
 
+        Ok(true)
+    }
+
+    fn erased_readback_check(&mut self, address: u32, size: u32) -> Result<(), Error<S>> {
+        for offset in (0..size).step_by(64) {
+            self.readback_check(address + offset, &[0xFF; 64])?;
+        }
+
         Ok(())
     }
 
+    /// Erases a single sector of flash memory with the size of SECTOR_SIZE.
+    ///
+    /// # Arguments
+    /// * `index` - the index of the sector that needs to be erased. The address of the first byte of the sector is the provided index * SECTOR_SIZE.
+    pub fn erase_sector(&mut self, index: u32) -> Result<(), Error<S>> {
+        self.erase(EraseUnit::Sector, index)
+    }
+
+    /// Erases a single block of flash memory with the size of BLOCK_32K_SIZE.
+    ///
+    /// # Arguments
+    /// * `index` - the index of the block that needs to be erased. The address of the first byte of the block is the provided index * BLOCK_32K_SIZE.
+    pub fn erase_block_32k(&mut self, index: u32) -> Result<(), Error<S>> {
+        self.erase(EraseUnit::Block32K, index)
+    }
+
+    /// Erases a single block of flash memory with the size of BLOCK_64K_SIZE.
+    ///
+    /// # Arguments
+    /// * `index` - the index of the block that needs to be erased. The address of the first byte of the block is the provided index * BLOCK_64K_SIZE.
+    pub fn erase_block_64k(&mut self, index: u32) -> Result<(), Error<S>> {
+        self.erase(EraseUnit::Block64K, index)
+    }
+
+    /// Starts erasing a single sector and returns without waiting for the erase to finish.
+    /// Use [Self::poll] or [Self::is_done] to find out when the erase is done.
+    ///
+    /// # Arguments
+    /// * `index` - the index of the sector that needs to be erased. The address of the first byte of the sector is the provided index * SECTOR_SIZE.
+    pub fn start_erase_sector(
+        &mut self,
+        index: u32,
+    ) -> Result<PendingOperation<'static>, Error<S>> {
+        self.send_erase(EraseUnit::Sector, index)
+    }
+
+    /// Starts erasing a single block of BLOCK_32K_SIZE and returns without waiting for the erase to finish.
+    /// Use [Self::poll] or [Self::is_done] to find out when the erase is done.
+    ///
+    /// # Arguments
+    /// * `index` - the index of the block that needs to be erased. The address of the first byte of the block is the provided index * BLOCK_32K_SIZE.
+    pub fn start_erase_block_32k(
+        &mut self,
+        index: u32,
+    ) -> Result<PendingOperation<'static>, Error<S>> {
+        self.send_erase(EraseUnit::Block32K, index)
+    }
+
+    /// Starts erasing a single block of BLOCK_64K_SIZE and returns without waiting for the erase to finish.
+    /// Use [Self::poll] or [Self::is_done] to find out when the erase is done.
+    ///
+    /// # Arguments
+    /// * `index` - the index of the block that needs to be erased. The address of the first byte of the block is the provided index * BLOCK_64K_SIZE.
+    pub fn start_erase_block_64k(
+        &mut self,
+        index: u32,
+    ) -> Result<PendingOperation<'static>, Error<S>> {
+        self.send_erase(EraseUnit::Block64K, index)
+    }
+
+    /// Erases all sectors on the flash chip.
+    /// This is a very expensive operation.
+    pub fn erase_chip(&mut self) -> Result<(), Error<S>> {
+        let operation = self.start_erase_chip()?;
+        self.finish(operation)
+    }
+
+    /// Starts erasing all sectors and returns without waiting for the erase to finish.
+    /// Use [Self::poll] or [Self::is_done] to find out when the erase is done.
+    pub fn start_erase_chip(&mut self) -> Result<PendingOperation<'static>, Error<S>> {
+        self.check_not_protected(0..self.geometry.capacity())?;
+        self.send_chip_erase()
+    }
+
+    /// Sends the chip erase instruction, without checking the protection or waiting for the erase to finish.
+    fn send_chip_erase(&mut self) -> Result<PendingOperation<'static>, Error<S>> {
+        self.enable_write()?;
+
+        self.spi
+            .execute(self.instruction(Command::ChipErase as u8))?;
+
+        Ok(PendingOperation::erase(
+            0,
+            self.geometry.capacity(),
+            BusyOperation::ChipErase,
+        ))
+    }
+
     /// Puts the chip into power down mode.
     /// While in the power-down state, only the Release Power-down/Device ID (0xAB) instruction will be recognized. This instruction restores the device to normal operation. All other instructions are ignored.
-    pub fn enable_power_down_mode(&mut self) -> Result<(), Error<S, P>> {
+    pub fn enable_power_down_mode(&mut self) -> Result<(), Error<S>> {
         self.spi
-            .write(&[Command::PowerDown as u8])
-            .map_err(Error::SpiError)?;
+            .execute(self.instruction(Command::PowerDown as u8))?;
 
         Ok(())
     }
 
     /// Releases the chip from power down mode.
     /// Restores operation from power down mode by reading the deviceID from the device.
-    pub fn disable_power_down_mode(&mut self) -> Result<(), Error<S, P>> {
+    pub fn disable_power_down_mode(&mut self) -> Result<(), Error<S>> {
         self.spi
-            .write(&[Command::ReleasePowerDown as u8])
-            .map_err(Error::SpiError)?;
+            .execute(self.instruction(Command::ReleasePowerDown as u8))?;
+
+        Ok(())
+    }
+}
+
+impl<SPI, S: Debug, HOLD, WP, DELAY> W25q32jv<SPI, HOLD, WP, DELAY>
+where
+    SPI: Transport<Error = S>,
+    HOLD: OptionalPin,
+    WP: OutputPin,
+    DELAY: PollDelay,
+    S: Debug,
+{
+    /// Set the SRP bit and drive the WP pin low, locking the status registers and with them the block protection
+    /// until [Self::exit_hardware_protection] is called.
+    ///
+    /// Returns [Error::Unsupported] if the QE bit is set, because the WP pin is then used as a data lane.
+    pub fn enter_hardware_protection(&mut self, volatility: Volatility) -> Result<(), Error<S>> {
+        if self.read_status_register_2()?.qe {
+            return Err(Error::Unsupported);
+        }
+
+        self.set_wp(PinState::High)?;
+        let status = self.read_status_register_1()?;
+        self.write_status_register_1(
+            StatusRegister1 {
+                srp: true,
+                ..status
+            },
+            volatility,
+        )?;
+        self.set_wp(PinState::Low)?;
 
         Ok(())
     }
+
+    /// Drive the WP pin high and clear the SRP bit, so the status registers can be written again.
+    pub fn exit_hardware_protection(&mut self, volatility: Volatility) -> Result<(), Error<S>> {
+        self.set_wp(PinState::High)?;
+        let status = self.read_status_register_1()?;
+        self.write_status_register_1(
+            StatusRegister1 {
+                srp: false,
+                ..status
+            },
+            volatility,
+        )
+    }
+}
+
+impl<SPI, S: Debug, HOLD, WP, DELAY> W25q32jv<SPI, HOLD, WP, DELAY>
+where
+    SPI: Transport<Error = S>,
+    HOLD: OptionalPin,
+    WP: OptionalPin,
+    DELAY: DelayNs,
+    S: Debug,
+{
+    /// Suspends the erase or program that is in progress, so the chip can execute other instructions like reads.
+    /// Returns `false` if nothing was suspended, because no erase or program was in progress.
+    ///
+    /// A suspended erase or program must be resumed with [Self::resume]. The data in the sector or page that
+    /// is being erased or programmed is undefined while suspended.
+    pub fn suspend(&mut self) -> Result<bool, Error<S>> {
+        if !self.busy()? {
+            return Ok(false);
+        }
+
+        self.spi
+            .execute(self.instruction(Command::EraseProgramSuspend as u8))?;
+
+        self.wait_busy(BusyOperation::Suspend)?;
+
+        Ok(self.read_status_register_2()?.sus)
+    }
+
+    /// Resumes the suspended erase or program. Returns `false` if nothing was suspended.
+    ///
+    /// Returns after the minimum time of 20 µs between a resume and the next suspend, so the erase or program
+    /// can't be starved by suspending it again right away.
+    pub fn resume(&mut self) -> Result<bool, Error<S>> {
+        if !self.read_status_register_2()?.sus {
+            return Ok(false);
+        }
+
+        self.spi
+            .execute(self.instruction(Command::EraseProgramResume as u8))?;
+        self.delay.delay_us(RESUME_TO_SUSPEND_US);
+
+        Ok(true)
+    }
 }
@@ -0,0 +1,134 @@
+//! Operation timing on the virtual clock: the polls of the status while busy, timeouts, and suspend and resume.
+//!
+//! Run with `cargo test --features sim --target <host triple>`.
+
+use core::convert::Infallible;
+use w25q32jv::sim::clock::{SimDelay, Timing, VirtualClock};
+use w25q32jv::sim::trace::{Recorder, TraceLog};
+use w25q32jv::sim::SimChip;
+use w25q32jv::{FlashGeometry, NoPin, W25q32jv, PAGE_SIZE, SECTOR_SIZE};
+
+type Flash = W25q32jv<Recorder<SimChip>, NoPin, NoPin, SimDelay>;
+type Error = w25q32jv::Error<Infallible>;
+
+const GEOMETRY: FlashGeometry = FlashGeometry::W25Q32;
+const READ_STATUS_REGISTER_1: u8 = 0x05;
+
+fn new_flash(timing: Timing) -> (Flash, VirtualClock, TraceLog) {
+    let clock = VirtualClock::new();
+    let log = TraceLog::new();
+    let chip = SimChip::new(GEOMETRY).with_clock(clock.clone(), timing);
+    let flash = W25q32jv::new_without_pins(Recorder::new(chip, log.clone()), GEOMETRY)
+        .unwrap()
+        .with_delay(clock.delay());
+
+    (flash, clock, log)
+}
+
+/// The number of status register 1 reads right after the last transaction with the opcode.
+fn polls_after(log: &TraceLog, opcode: u8) -> usize {
+    let transactions = log.take().transactions;
+    let start = transactions
+        .iter()
+        .rposition(|transaction| transaction.opcode == opcode)
+        .unwrap();
+
+    transactions[start + 1..]
+        .iter()
+        .take_while(|transaction| transaction.opcode == READ_STATUS_REGISTER_1)
+        .count()
+}
+
+#[test]
+fn page_program_polls() {
+    let (mut flash, clock, log) = new_flash(Timing::Typical);
+    log.take();
+
+    flash
+        .write_blocking(0, &[0x5A; PAGE_SIZE as usize])
+        .unwrap();
+
+    // The typical 400 µs at intervals of 50 µs, plus the read that finds it done
+    assert_eq!(polls_after(&log, 0x02), 9);
+    assert_eq!(clock.now_us(), 400);
+}
+
+#[test]
+fn sector_erase_polls() {
+    let (mut flash, clock, log) = new_flash(Timing::Typical);
+    log.take();
+
+    flash.erase_range(0, SECTOR_SIZE).unwrap();
+
+    // The typical 45 ms at intervals of 1 ms, plus the read that finds it done
+    assert_eq!(polls_after(&log, 0x20), 46);
+    assert_eq!(clock.now_us(), 45_000);
+}
+
+#[test]
+fn sector_erase_within_maximum() {
+    let (mut flash, clock, _log) = new_flash(Timing::Maximum);
+
+    flash.erase_range(0, SECTOR_SIZE).unwrap();
+
+    assert_eq!(clock.now_us(), 400_000);
+}
+
+#[test]
+fn erase_chip_timeout() {
+    let (mut flash, clock, log) = new_flash(Timing::Slow(50));
+    log.take();
+
+    assert!(matches!(flash.erase_chip(), Err(Error::Timeout)));
+
+    // The maximum of 50 s plus the default margin of 25 %, and the interval of 100 ms that crossed it
+    let timeout_us = 50_000_000 * 125 / 100;
+    assert_eq!(clock.now_us(), timeout_us + 100_000);
+    assert_eq!(polls_after(&log, 0xC7), 627);
+
+    // The chip is still erasing
+    assert!(flash.read_status_register_1().unwrap().busy);
+}
+
+#[test]
+fn erase_chip_without_timeout() {
+    let (mut flash, clock, _log) = new_flash(Timing::Slow(50));
+    flash.set_timeout_margin(None);
+
+    flash.erase_chip().unwrap();
+
+    assert_eq!(clock.now_us(), 75_000_000);
+}
+
+#[test]
+fn suspend_and_resume_timing() {
+    let (mut flash, clock, _log) = new_flash(Timing::Typical);
+
+    let erase = flash.start_erase_sector(1).unwrap();
+    clock.advance_ns(10_000_000);
+
+    // Suspending takes tSUS, which the driver polls for at intervals of 5 µs
+    assert!(flash.suspend().unwrap());
+    assert_eq!(clock.now_us(), 10_020);
+    let mut buf = [0; 4];
+    flash.read(0, &mut buf).unwrap();
+    assert_eq!(buf, [0xFF; 4]);
+
+    // The time while suspended doesn't count towards the erase
+    clock.advance_ns(1_000_000);
+    assert!(flash.resume().unwrap());
+    assert_eq!(clock.now_us(), 11_040);
+    assert!(!flash.resume().unwrap());
+
+    // Resume returns after the minimum time before the next suspend, so suspending right away is allowed
+    assert!(flash.suspend().unwrap());
+    assert!(flash.resume().unwrap());
+    assert_eq!(clock.now_us(), 11_080);
+
+    // 35 ms of the erase were left at the first suspend, and the erase ran during the two resumes
+    clock.advance_ns(34_959_000);
+    assert!(!flash.poll(&erase).unwrap());
+    clock.advance_ns(1_000);
+    assert!(flash.poll(&erase).unwrap());
+    assert!(!flash.suspend().unwrap());
+}
@@ -0,0 +1,665 @@
+//! The same scenarios run on the simulated chip through the blocking and the async driver,
+//! and through the `embedded-storage` traits on top of them, for the 32 to 512 M-bit chips.
+//!
+//! Run with `cargo test --features sim --target <host triple>`.
+
+use core::convert::Infallible;
+use core::future::Future;
+use core::pin::pin;
+use core::task::{Context, Poll, Waker};
+use w25q32jv::sim::SimChip;
+use w25q32jv::status::{DriverStrength, StatusRegister1, StatusRegister3, Volatility};
+use w25q32jv::{FlashGeometry, NoPin, W25q32jv, BLOCK_64K_SIZE, PAGE_SIZE, SECTOR_SIZE};
+
+type Flash<SPI = SimChip> = W25q32jv<SPI, NoPin, NoPin>;
+type Error = w25q32jv::Error<Infallible>;
+
+const GEOMETRIES: [FlashGeometry; 5] = [
+    FlashGeometry::W25Q32,
+    FlashGeometry::W25Q64,
+    FlashGeometry::W25Q128,
+    FlashGeometry::W25Q256,
+    FlashGeometry::W25Q512,
+];
+
+/// Run a future to completion. The simulated chip never makes the driver wait.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut context = Context::from_waker(Waker::noop());
+
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+    }
+}
+
+/// The operations the scenarios use, implemented by each API of the driver.
+#[allow(async_fn_in_trait)]
+trait Api: Sized {
+    async fn new(chip: SimChip) -> Result<Self, Error>;
+    fn flash(&self) -> &Flash;
+    async fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error>;
+    async fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Error>;
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Error>;
+    async fn power_down(&mut self) -> Result<(), Error>;
+    async fn power_up(&mut self) -> Result<(), Error>;
+}
+
+/// The inherent methods of the blocking driver.
+struct Blocking(Flash);
+
+impl Api for Blocking {
+    async fn new(chip: SimChip) -> Result<Self, Error> {
+        W25q32jv::new_detected(chip, NoPin, NoPin).map(Self)
+    }
+
+    fn flash(&self) -> &Flash {
+        &self.0
+    }
+
+    async fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error> {
+        self.0.read(address, buf)
+    }
+
+    async fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+        self.0.write_blocking(address, data)
+    }
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        Ok(self.0.erase_range(from, to)?)
+    }
+
+    async fn power_down(&mut self) -> Result<(), Error> {
+        self.0.enable_power_down_mode()
+    }
+
+    async fn power_up(&mut self) -> Result<(), Error> {
+        self.0.disable_power_down_mode()
+    }
+}
+
+/// The blocking `embedded-storage` traits.
+struct Storage(Flash);
+
+impl Api for Storage {
+    async fn new(chip: SimChip) -> Result<Self, Error> {
+        W25q32jv::new_detected(chip, NoPin, NoPin).map(Self)
+    }
+
+    fn flash(&self) -> &Flash {
+        &self.0
+    }
+
+    async fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error> {
+        embedded_storage::nor_flash::ReadNorFlash::read(&mut self.0, address, buf)
+    }
+
+    async fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+        embedded_storage::nor_flash::NorFlash::write(&mut self.0, address, data)
+    }
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        embedded_storage::nor_flash::NorFlash::erase(&mut self.0, from, to)
+    }
+
+    async fn power_down(&mut self) -> Result<(), Error> {
+        self.0.enable_power_down_mode()
+    }
+
+    async fn power_up(&mut self) -> Result<(), Error> {
+        self.0.disable_power_down_mode()
+    }
+}
+
+/// The inherent methods of the async driver.
+#[cfg(feature = "async")]
+struct Async(Flash);
+
+#[cfg(feature = "async")]
+impl Api for Async {
+    async fn new(chip: SimChip) -> Result<Self, Error> {
+        W25q32jv::new_detected_async(chip, NoPin, NoPin)
+            .await
+            .map(Self)
+    }
+
+    fn flash(&self) -> &Flash {
+        &self.0
+    }
+
+    async fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error> {
+        self.0.read_async(address, buf).await
+    }
+
+    async fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+        self.0.write_async(address, data).await
+    }
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        Ok(self.0.erase_range_async(from, to).await?)
+    }
+
+    async fn power_down(&mut self) -> Result<(), Error> {
+        self.0.enable_power_down_mode_async().await
+    }
+
+    async fn power_up(&mut self) -> Result<(), Error> {
+        self.0.disable_power_down_mode_async().await
+    }
+}
+
+/// The async `embedded-storage-async` traits.
+#[cfg(feature = "async")]
+struct AsyncStorage(Flash);
+
+#[cfg(feature = "async")]
+impl Api for AsyncStorage {
+    async fn new(chip: SimChip) -> Result<Self, Error> {
+        W25q32jv::new_detected_async(chip, NoPin, NoPin)
+            .await
+            .map(Self)
+    }
+
+    fn flash(&self) -> &Flash {
+        &self.0
+    }
+
+    async fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error> {
+        embedded_storage_async::nor_flash::ReadNorFlash::read(&mut self.0, address, buf).await
+    }
+
+    async fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+        embedded_storage_async::nor_flash::NorFlash::write(&mut self.0, address, data).await
+    }
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        embedded_storage_async::nor_flash::NorFlash::erase(&mut self.0, from, to).await
+    }
+
+    async fn power_down(&mut self) -> Result<(), Error> {
+        self.0.enable_power_down_mode_async().await
+    }
+
+    async fn power_up(&mut self) -> Result<(), Error> {
+        self.0.disable_power_down_mode_async().await
+    }
+}
+
+/// Generate a test per API for each scenario, which runs it for every geometry on a fresh chip.
+macro_rules! scenarios {
+    ($($scenario:ident),* $(,)?) => {
+        $(
+            mod $scenario {
+                use super::*;
+
+                fn run<A: Api>() {
+                    for geometry in GEOMETRIES {
+                        let chip = SimChip::new(geometry);
+                        block_on(async {
+                            let flash = A::new(chip.clone()).await.unwrap();
+                            super::$scenario(flash, chip).await;
+                        });
+                    }
+                }
+
+                #[test]
+                fn blocking() {
+                    run::<Blocking>();
+                }
+
+                #[test]
+                fn storage() {
+                    run::<Storage>();
+                }
+
+                #[cfg(feature = "async")]
+                #[test]
+                fn async_driver() {
+                    run::<Async>();
+                }
+
+                #[cfg(feature = "async")]
+                #[test]
+                fn async_storage() {
+                    run::<AsyncStorage>();
+                }
+            }
+        )*
+    };
+}
+
+scenarios!(
+    detects_geometry,
+    writes_across_page_boundaries,
+    writes_up_to_the_end,
+    rejects_out_of_bounds,
+    rejects_unaligned_erase,
+    erases_only_the_range,
+    readback_failure,
+    power_down,
+);
+
+/// A pattern that differs per byte and per call, so misplaced bytes are noticed.
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed))
+        .collect()
+}
+
+fn memory(chip: &SimChip, address: u32, len: usize) -> Vec<u8> {
+    let mut buf = vec![0; len];
+    chip.read_memory(address, &mut buf);
+    buf
+}
+
+async fn detects_geometry(flash: impl Api, chip: SimChip) {
+    assert_eq!(flash.flash().geometry(), chip.geometry());
+}
+
+async fn writes_across_page_boundaries(mut flash: impl Api, chip: SimChip) {
+    // Starts 7 bytes before the end of a page and ends in the middle of the third page after it
+    let address = 3 * PAGE_SIZE - 7;
+    let data = pattern(600, 1);
+
+    flash.write(address, &data).await.unwrap();
+
+    let mut buf = vec![0; data.len()];
+    flash.read(address, &mut buf).await.unwrap();
+    assert_eq!(buf, data);
+    assert_eq!(memory(&chip, address, data.len()), data);
+
+    // The bytes around the write are untouched
+    assert_eq!(memory(&chip, address - 1, 1), [0xFF]);
+    assert_eq!(memory(&chip, address + data.len() as u32, 1), [0xFF]);
+
+    // A write that ends exactly on a page boundary
+    let data = pattern(PAGE_SIZE as usize + 16, 2);
+    let address = 8 * PAGE_SIZE - 16;
+    flash.write(address, &data).await.unwrap();
+    assert_eq!(memory(&chip, address, data.len()), data);
+    assert_eq!(memory(&chip, 9 * PAGE_SIZE, 1), [0xFF]);
+}
+
+async fn writes_up_to_the_end(mut flash: impl Api, chip: SimChip) {
+    let capacity = chip.geometry().capacity();
+    let data = pattern(300, 3);
+    let address = capacity - data.len() as u32;
+
+    flash.write(address, &data).await.unwrap();
+
+    let mut buf = vec![0; data.len()];
+    flash.read(address, &mut buf).await.unwrap();
+    assert_eq!(buf, data);
+
+    flash.erase(capacity - SECTOR_SIZE, capacity).await.unwrap();
+    assert!(memory(&chip, capacity - SECTOR_SIZE, SECTOR_SIZE as usize)
+        .iter()
+        .all(|&byte| byte == 0xFF));
+}
+
+async fn rejects_out_of_bounds(mut flash: impl Api, chip: SimChip) {
+    let capacity = chip.geometry().capacity();
+    let mut buf = [0; 2];
+
+    assert!(matches!(
+        flash.read(capacity - 1, &mut buf).await,
+        Err(Error::OutOfBounds)
+    ));
+    assert!(matches!(
+        flash.read(u32::MAX, &mut buf).await,
+        Err(Error::OutOfBounds)
+    ));
+    assert!(matches!(
+        flash.write(capacity - 1, &[0; 2]).await,
+        Err(Error::OutOfBounds)
+    ));
+    assert!(matches!(
+        flash.write(u32::MAX, &[0; 2]).await,
+        Err(Error::OutOfBounds)
+    ));
+    assert!(matches!(
+        flash
+            .erase(capacity - SECTOR_SIZE, capacity + SECTOR_SIZE)
+            .await,
+        Err(Error::OutOfBounds)
+    ));
+    assert!(matches!(
+        flash.erase(2 * SECTOR_SIZE, SECTOR_SIZE).await,
+        Err(Error::OutOfBounds)
+    ));
+
+    // Nothing was written or erased at the end of the chip
+    chip.write_memory(capacity - 1, &[0x00]);
+    assert!(matches!(
+        flash
+            .erase(capacity - SECTOR_SIZE, capacity + SECTOR_SIZE)
+            .await,
+        Err(Error::OutOfBounds)
+    ));
+    assert_eq!(memory(&chip, capacity - 2, 2), [0xFF, 0x00]);
+}
+
+async fn rejects_unaligned_erase(mut flash: impl Api, chip: SimChip) {
+    let data = pattern(2 * SECTOR_SIZE as usize, 4);
+    flash.write(0, &data).await.unwrap();
+
+    assert!(matches!(
+        flash.erase(1, SECTOR_SIZE).await,
+        Err(Error::NotAligned)
+    ));
+    assert!(matches!(
+        flash.erase(0, SECTOR_SIZE - 1).await,
+        Err(Error::NotAligned)
+    ));
+    assert!(matches!(
+        flash.erase(SECTOR_SIZE / 2, 2 * SECTOR_SIZE).await,
+        Err(Error::NotAligned)
+    ));
+
+    assert_eq!(memory(&chip, 0, data.len()), data);
+}
+
+async fn erases_only_the_range(mut flash: impl Api, chip: SimChip) {
+    // Spans a 64K block boundary, so both sectors and a block are erased
+    let start = BLOCK_64K_SIZE - SECTOR_SIZE;
+    let end = 2 * BLOCK_64K_SIZE + SECTOR_SIZE;
+    let before = pattern(16, 5);
+    let after = pattern(16, 6);
+
+    flash.write(start - 16, &before).await.unwrap();
+    flash.write(start, &pattern(32, 7)).await.unwrap();
+    flash
+        .write(BLOCK_64K_SIZE + 100, &pattern(32, 8))
+        .await
+        .unwrap();
+    flash.write(end - 32, &pattern(32, 9)).await.unwrap();
+    flash.write(end, &after).await.unwrap();
+
+    flash.erase(start, end).await.unwrap();
+
+    assert!(memory(&chip, start, (end - start) as usize)
+        .iter()
+        .all(|&byte| byte == 0xFF));
+    assert_eq!(memory(&chip, start - 16, 16), before);
+    assert_eq!(memory(&chip, end, 16), after);
+
+    let mut buf = [0; 16];
+    flash.read(end, &mut buf).await.unwrap();
+    assert_eq!(buf, *after);
+}
+
+async fn readback_failure(mut flash: impl Api, chip: SimChip) {
+    flash.write(10, &[0x00]).await.unwrap();
+
+    // Programming can't set bits, so the read back byte differs
+    let result = flash.write(10, &[0xF0]).await;
+    if cfg!(feature = "readback-check") {
+        assert!(matches!(result, Err(Error::ReadbackFail)));
+    } else {
+        assert!(result.is_ok());
+    }
+    assert_eq!(memory(&chip, 10, 1), [0x00]);
+
+    // Clearing more bits of a programmed byte is fine
+    flash.write(20, &[0xF0]).await.unwrap();
+    flash.write(20, &[0x30]).await.unwrap();
+    assert_eq!(memory(&chip, 20, 1), [0x30]);
+}
+
+async fn power_down(mut flash: impl Api, chip: SimChip) {
+    let data = pattern(64, 10);
+    flash.write(0, &data).await.unwrap();
+
+    flash.power_down().await.unwrap();
+    assert!(chip.is_powered_down());
+
+    // The chip ignores the read instruction, so nothing drives the data line
+    let mut buf = [0; 64];
+    flash.read(0, &mut buf).await.unwrap();
+    assert!(buf.iter().all(|&byte| byte == 0xFF));
+
+    flash.power_up().await.unwrap();
+    assert!(!chip.is_powered_down());
+
+    flash.read(0, &mut buf).await.unwrap();
+    assert_eq!(buf, *data);
+}
+
+/// A read-modify-write of status register 3 keeps the ADP bit of the 4-byte address chips.
+#[test]
+fn status_register_3_keeps_unknown_bits() {
+    let chip = SimChip::new(FlashGeometry::W25Q256);
+    let mut flash = W25q32jv::new_without_pins(chip.clone(), FlashGeometry::W25Q256).unwrap();
+
+    let status = flash.read_status_register_3().unwrap();
+    let adp = StatusRegister3 {
+        other_bits: status.other_bits | 0x02,
+        ..status
+    };
+    flash
+        .write_status_register_3(adp, Volatility::NonVolatile)
+        .unwrap();
+
+    let status = flash.read_status_register_3().unwrap();
+    let drv = StatusRegister3 {
+        drv: DriverStrength::Percent50,
+        ..status
+    };
+    flash
+        .write_status_register_3(drv, Volatility::NonVolatile)
+        .unwrap();
+    assert_eq!(flash.read_status_register_3().unwrap(), drv);
+
+    // ADP makes the chip start in 4-byte address mode, which sets ADS
+    chip.power_cycle();
+    assert_eq!(flash.read_status_register_3().unwrap().other_bits, 0x03);
+}
+
+/// The protected range of a chip without supported block protection bits is only known while all bits are clear.
+#[test]
+fn unsupported_block_protection_bits() {
+    let chip = SimChip::new(FlashGeometry::W25Q256);
+    let mut flash = W25q32jv::new_without_pins(chip, FlashGeometry::W25Q256).unwrap();
+
+    flash.write_blocking(0, &[0x12]).unwrap();
+
+    let status = flash.read_status_register_1().unwrap();
+    let bp0 = StatusRegister1 { bp: 1, ..status };
+    flash
+        .write_status_register_1(bp0, Volatility::Volatile)
+        .unwrap();
+    assert!(matches!(
+        flash.write_blocking(0, &[0x34]),
+        Err(Error::Unsupported)
+    ));
+    assert!(matches!(
+        flash.erase_range(0, SECTOR_SIZE),
+        Err(w25q32jv::EraseRangeError {
+            error: Error::Unsupported,
+            ..
+        })
+    ));
+
+    flash
+        .write_status_register_1(status, Volatility::Volatile)
+        .unwrap();
+    flash.erase_range(0, SECTOR_SIZE).unwrap();
+}
+
+/// The transactions of reads, writes and erases, pinned so changes to the wire behaviour are noticed.
+mod wire {
+    use super::*;
+    use embedded_hal::spi::SpiDevice;
+    use w25q32jv::sim::trace::{Recorder, Replayer, Trace, TraceLog};
+    use w25q32jv::transport::{Instruction, Lanes, Transport};
+    use w25q32jv::AddressWidth;
+
+    /// Reads on the 512 M-bit chip with the 4-byte address instruction, around a power down.
+    const READ_TRACE: &str = "
+        13 @02FFFFD4 r300
+        B9
+        AB
+        13 @00000100 r4
+    ";
+
+    /// A write across a page boundary: every page is programmed, waited for and read back.
+    #[cfg(feature = "readback-check")]
+    const WRITE_TRACE: &str = "
+        15 r1
+        05 r1
+        35 r1
+        06
+        05 r1
+        02 @0000FE w2
+        05 r1
+        05 r1
+        03 @0000FE r2
+        06
+        05 r1
+        02 @000100 w2
+        05 r1
+        05 r1
+        03 @000100 r2
+    ";
+
+    /// An erase of the second sector, waited for and followed by a blank check in reads of 64 bytes.
+    #[cfg(feature = "readback-check")]
+    fn erase_trace() -> String {
+        let mut trace = String::from(
+            "
+            15 r1
+            05 r1
+            35 r1
+            06
+            05 r1
+            20 @001000
+            05 r1
+            05 r1
+            ",
+        );
+        for address in (SECTOR_SIZE..2 * SECTOR_SIZE).step_by(64) {
+            trace += &format!("03 @{address:06X} r64\n");
+        }
+        trace
+    }
+
+    fn replayer(geometry: FlashGeometry, trace: &str) -> Flash<Replayer<SimChip>> {
+        let spi = Replayer::new(SimChip::new(geometry), trace.parse::<Trace>().unwrap());
+        W25q32jv::new_without_pins(spi, geometry).unwrap()
+    }
+
+    #[test]
+    fn blocking_read() {
+        let mut flash = replayer(FlashGeometry::W25Q512, READ_TRACE);
+        let mut buf = [0; 300];
+        flash.read(0x0300_0000 - 44, &mut buf).unwrap();
+        flash.enable_power_down_mode().unwrap();
+        flash.disable_power_down_mode().unwrap();
+        flash.read(0x100, &mut buf[..4]).unwrap();
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_read() {
+        block_on(async {
+            let mut flash = replayer(FlashGeometry::W25Q512, READ_TRACE);
+            let mut buf = [0; 300];
+            flash.read_async(0x0300_0000 - 44, &mut buf).await.unwrap();
+            flash.enable_power_down_mode_async().await.unwrap();
+            flash.disable_power_down_mode_async().await.unwrap();
+            flash.read_async(0x100, &mut buf[..4]).await.unwrap();
+        });
+    }
+
+    #[cfg(feature = "readback-check")]
+    #[test]
+    fn blocking_write_and_erase() {
+        let mut flash = replayer(FlashGeometry::W25Q32, WRITE_TRACE);
+        flash.write_blocking(PAGE_SIZE - 2, &[1, 2, 3, 4]).unwrap();
+
+        let mut flash = replayer(FlashGeometry::W25Q32, &erase_trace());
+        flash.erase_range(SECTOR_SIZE, 2 * SECTOR_SIZE).unwrap();
+    }
+
+    #[cfg(all(feature = "async", feature = "readback-check"))]
+    #[test]
+    fn async_write_and_erase() {
+        block_on(async {
+            let mut flash = replayer(FlashGeometry::W25Q32, WRITE_TRACE);
+            flash
+                .write_async(PAGE_SIZE - 2, &[1, 2, 3, 4])
+                .await
+                .unwrap();
+
+            let mut flash = replayer(FlashGeometry::W25Q32, &erase_trace());
+            flash
+                .erase_range_async(SECTOR_SIZE, 2 * SECTOR_SIZE)
+                .await
+                .unwrap();
+        });
+    }
+
+    /// Writes across a page boundary, erases a sector and reads it back around a power down.
+    fn session<SPI: SpiDevice<Error = Infallible>>(flash: &mut Flash<SPI>) -> Vec<u8> {
+        let data = pattern(PAGE_SIZE as usize, 3);
+        flash.write_blocking(PAGE_SIZE / 2, &data).unwrap();
+        flash.erase_range(SECTOR_SIZE, 2 * SECTOR_SIZE).unwrap();
+        flash.enable_power_down_mode().unwrap();
+        flash.disable_power_down_mode().unwrap();
+
+        let mut buf = vec![0; data.len()];
+        flash.read(PAGE_SIZE / 2, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let log = TraceLog::new();
+        let spi = Recorder::new(SimChip::new(FlashGeometry::W25Q32), log.clone());
+        let mut flash = W25q32jv::new_without_pins(spi, FlashGeometry::W25Q32).unwrap();
+        let data = session(&mut flash);
+        assert_eq!(data, pattern(PAGE_SIZE as usize, 3));
+
+        let trace = log.take();
+        assert_eq!(trace.to_string().parse::<Trace>().unwrap(), trace);
+
+        // Replaying the same session on a new chip sends the same transactions, and all of them
+        let mut flash = replayer(FlashGeometry::W25Q32, &trace.to_string());
+        assert_eq!(session(&mut flash), data);
+        drop(flash);
+    }
+
+    #[test]
+    #[should_panic(expected = "transaction 6 is `02 @000100 w2`, expected `02 @000080 w2`")]
+    fn replay_of_different_traffic() {
+        let log = TraceLog::new();
+        let spi = Recorder::new(SimChip::new(FlashGeometry::W25Q32), log.clone());
+        let mut flash = W25q32jv::new_without_pins(spi, FlashGeometry::W25Q32).unwrap();
+        flash.write_blocking(PAGE_SIZE / 2, &[1, 2]).unwrap();
+
+        // The same write, to a different address
+        let mut flash = replayer(FlashGeometry::W25Q32, &log.take().to_string());
+        flash.write_blocking(PAGE_SIZE, &[1, 2]).unwrap();
+    }
+
+    /// Instructions a single-lane SPI device can't send are rejected before anything is sent.
+    #[test]
+    fn unsupported_single_lane_instructions() {
+        let mut spi = Replayer::new(SimChip::new(FlashGeometry::W25Q32), "".parse().unwrap());
+
+        let too_many_dummy_cycles = Instruction::new(0x0B)
+            .with_address(0, AddressWidth::ThreeByte)
+            .with_dummy_cycles(72);
+        assert!(matches!(
+            spi.execute(too_many_dummy_cycles),
+            Err(Error::Unsupported)
+        ));
+
+        let dual_output = Instruction::new(0x3B)
+            .with_address(0, AddressWidth::ThreeByte)
+            .with_dummy_cycles(8)
+            .with_lanes(Lanes::Single, Lanes::Single, Lanes::Dual);
+        assert!(matches!(spi.execute(dual_output), Err(Error::Unsupported)));
+    }
+}
@@ -0,0 +1,116 @@
+//! Power loss during programs and erases on the simulated chip, and recovery with a new driver after power up.
+//!
+//! Run with `cargo test --features sim --target <host triple>`.
+
+use w25q32jv::sim::fault::{FaultyChip, PowerLoss};
+use w25q32jv::sim::SimChip;
+use w25q32jv::{EraseRangeError, FlashGeometry, NoPin, W25q32jv, PAGE_SIZE, SECTOR_SIZE};
+
+type Flash = W25q32jv<FaultyChip, NoPin, NoPin>;
+type Error = w25q32jv::Error<PowerLoss>;
+
+const GEOMETRY: FlashGeometry = FlashGeometry::W25Q32;
+const SEED: u64 = 7;
+
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed))
+        .collect()
+}
+
+fn memory(chip: &SimChip, address: u32, len: usize) -> Vec<u8> {
+    let mut buf = vec![0; len];
+    chip.read_memory(address, &mut buf);
+    buf
+}
+
+fn new_flash(chip: &FaultyChip) -> Flash {
+    W25q32jv::new_without_pins(chip.clone(), GEOMETRY).unwrap()
+}
+
+#[test]
+fn power_loss_during_page_program() {
+    let chip = FaultyChip::new(SimChip::new(GEOMETRY), SEED);
+    let mut flash = new_flash(&chip);
+    let address = 4 * PAGE_SIZE;
+    let data = pattern(PAGE_SIZE as usize, 1);
+
+    chip.cut_power_during(0);
+    assert!(matches!(
+        flash.write_blocking(address, &data),
+        Err(Error::SpiError(PowerLoss))
+    ));
+    assert!(!chip.has_power());
+
+    // The program only cleared bits, but not all of the ones it had to
+    let programmed = memory(chip.chip(), address, data.len());
+    assert!(programmed
+        .iter()
+        .zip(&data)
+        .all(|(byte, data)| byte & data == *data));
+    assert_ne!(programmed, data);
+    assert!(programmed
+        .iter()
+        .zip(&data)
+        .any(|(byte, data)| byte != data && *byte != 0xFF));
+
+    // The pages around it are untouched
+    assert_eq!(memory(chip.chip(), address - 1, 1), [0xFF]);
+    assert_eq!(memory(chip.chip(), address + PAGE_SIZE, 1), [0xFF]);
+
+    // After power up a new driver erases the sector and programs the page again
+    chip.restore_power();
+    let mut flash = new_flash(&chip);
+    flash.erase_range(0, SECTOR_SIZE).unwrap();
+    flash.write_blocking(address, &data).unwrap();
+
+    let mut buf = vec![0; data.len()];
+    flash.read(address, &mut buf).unwrap();
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn power_loss_during_sector_erase() {
+    let chip = FaultyChip::new(SimChip::new(GEOMETRY), SEED);
+    let mut flash = new_flash(&chip);
+    let old = pattern(SECTOR_SIZE as usize, 2);
+    flash.write_blocking(SECTOR_SIZE, &old).unwrap();
+
+    chip.cut_power_during(0);
+    assert!(matches!(
+        flash.erase_range(SECTOR_SIZE, 2 * SECTOR_SIZE),
+        Err(EraseRangeError {
+            error: Error::SpiError(PowerLoss),
+            address,
+            erased_sectors: 0,
+        }) if address == SECTOR_SIZE
+    ));
+    assert!(!chip.has_power());
+
+    // The erase only set bits, but not all of them
+    let erased = memory(chip.chip(), SECTOR_SIZE, SECTOR_SIZE as usize);
+    assert!(erased
+        .iter()
+        .zip(&old)
+        .all(|(byte, old)| byte & old == *old));
+    assert!(erased.iter().any(|&byte| byte != 0xFF));
+    assert!(erased
+        .iter()
+        .zip(&old)
+        .any(|(byte, old)| byte != old && *byte != 0xFF));
+
+    // The sectors around it are untouched
+    assert_eq!(memory(chip.chip(), 0, 1), [0xFF]);
+    assert_eq!(memory(chip.chip(), 2 * SECTOR_SIZE, 1), [0xFF]);
+
+    // After power up a new driver erases the sector again, and it's blank
+    chip.restore_power();
+    let mut flash = new_flash(&chip);
+    flash.erase_range(SECTOR_SIZE, 2 * SECTOR_SIZE).unwrap();
+    assert!(memory(chip.chip(), SECTOR_SIZE, SECTOR_SIZE as usize)
+        .iter()
+        .all(|&byte| byte == 0xFF));
+
+    flash.write_blocking(SECTOR_SIZE, &old).unwrap();
+    assert_eq!(memory(chip.chip(), SECTOR_SIZE, old.len()), old);
+}